@@ -1,123 +1,548 @@
-use std::{collections::BTreeMap, path::PathBuf, time::SystemTime};
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
-use sqlite::{Connection, Value};
+use rayon::prelude::*;
+use sqlite::{Connection, State, Statement, Value};
 use walkdir::WalkDir;
 
+use crate::error::{Result, WingsError};
+use crate::explain::LoadStrategy;
+use crate::formats::{
+    chunk_files, read_chunk_batches, Chunk, CsvReader, FormatReader, FormatRegistry, Record,
+    CHUNK_SIZE,
+};
+use crate::metadata::{ErrorPolicy, Format, Index, TableMetadata, ROW_NUMBER_COLUMN};
+use crate::progress::Progress;
 use crate::rayon_loader;
+use crate::stats::{FileStats, FileStatsCollector, TableStats};
+use crate::utils::{
+    glob_match, has_wildcard, quote_identifier, quote_table_name, schema_to_db, split_table_name,
+};
 use crate::{for_loop_loader, metadata};
-use crate::{metadata::TableMetadata, utils::schema_to_db};
+
+/// Rows read from a single file, sent from the readers to the database writer
+pub type Batch = Vec<Record>;
+
+/// Number of rows a reader collects before sending them to the writer
+pub const BATCH_SIZE: usize = 1000;
+
+/// Number of batches that can wait for the writer before readers block
+const CHANNEL_CAPACITY: usize = 16;
+
+/// SQLite's result code for a row that breaks a constraint
+const SQLITE_CONSTRAINT: isize = 19;
 
 enum RunMode {
-    #[allow(dead_code)]
     ForLoop,
     Rayon,
 }
 
-pub fn load(connection: &Connection, tables: Vec<PathBuf>) {
-    let mode = RunMode::Rayon;
-
+pub fn load(connection: &Connection, tables: Vec<PathBuf>) -> Result<()> {
+    let formats = FormatRegistry::default();
     for table in tables {
-        let table_metadata = metadata::get_table_metadata(table).unwrap();
-        //   Get table schema
-        //   Create table in sqlite
-        create_table_from_metadata(connection, &table_metadata);
-
-        //   Get data_path and format
-        //   For all files in data_path with format (parallelize)
-        let data_path = table_metadata.metadata.data_path.clone();
-        let format = table_metadata.metadata.format.clone();
-
-        // Either specify fields in insert or make sure values are in the right order
-        let columns: Vec<String> = table_metadata.schema.keys().cloned().collect();
-        let values: Vec<String> = columns.iter().map(|f| format!(":{}", f)).collect();
-
-        let files: Vec<PathBuf> = WalkDir::new(data_path)
-            .into_iter()
-            .map(|f| return f.unwrap().path().to_path_buf())
-            .collect();
+        let table_metadata = metadata::get_table_metadata(table)?;
+        load_table(connection, &table_metadata, &formats, None, false)?;
+    }
+    Ok(())
+}
 
-        #[allow(unused_assignments)]
-        let mut rows = Vec::new();
-        match mode {
-            RunMode::ForLoop => {
-                rows = for_loop_loader::load(files, format);
-            }
-            RunMode::Rayon => {
-                rows = rayon_loader::load(files, format);
-            }
-        }
+/// Load a table's files into the database
+/// With a row limit, reading stops once that many rows have been inserted
+/// With progress on, loading is reported on stderr when it's a terminal
+pub fn load_table(
+    connection: &Connection,
+    table_metadata: &TableMetadata,
+    formats: &FormatRegistry,
+    row_limit: Option<u64>,
+    progress: bool,
+) -> Result<TableStats> {
+    let started = Instant::now();
+    let strategy = match row_limit {
+        Some(limit) => LoadStrategy::Limit(limit),
+        None => LoadStrategy::Full,
+    };
+    let mut stats = TableStats::new(&table_metadata.metadata.name, strategy);
 
-        if rows.len() == 0 {
-            println!("No rows found");
-            return;
+    //   Get table schema
+    //   Create table in sqlite
+    create_table_from_metadata(connection, table_metadata)?;
+    if let Err(error) = fill_table(
+        connection,
+        table_metadata,
+        formats,
+        row_limit,
+        progress,
+        &mut stats,
+    ) {
+        // Batches are committed as they're inserted, so the rows loaded before
+        // the failure are dropped for the next query to start over
+        let drop_table = format!(
+            "drop table if exists {}",
+            quote_table_name(&table_metadata.metadata.name)
+        );
+        if let Err(drop_error) = connection.execute(drop_table) {
+            log::warn!(
+                "Failed to drop {} after its load failed: {}",
+                table_metadata.metadata.name,
+                drop_error
+            );
         }
+        return Err(error);
+    }
+    stats.total_time = started.elapsed();
 
-        // Put flatten in loaders
-        let flat_rows = flatten(rows);
+    if stats.rows_inserted == 0 {
+        log::info!("No rows found for {}", table_metadata.metadata.name);
+    }
+    Ok(stats)
+}
 
-        // Do this in batches instead of single inserts
-        load_db(
-            connection,
-            &table_metadata.metadata.name,
-            columns,
-            values,
-            flat_rows,
+/// Read a table's files into the table created for it
+fn fill_table(
+    connection: &Connection,
+    table_metadata: &TableMetadata,
+    formats: &FormatRegistry,
+    row_limit: Option<u64>,
+    progress: bool,
+    stats: &mut TableStats,
+) -> Result<()> {
+    // A limited load usually needs only the start of the first file, and
+    // splitting reads every file through to find where its rows start, so
+    // files are read whole and in order instead
+    let mode = match row_limit {
+        Some(_) => RunMode::ForLoop,
+        None => RunMode::Rayon,
+    };
+
+    //   Get data_path and format
+    //   For all files in data_path with format (parallelize)
+    let reader = get_reader(table_metadata, formats)?;
+
+    let discovery = Instant::now();
+    let files = get_files(table_metadata)?;
+    let chunks = match mode {
+        // Files are read one at a time so there is nothing to gain from splitting them
+        RunMode::ForLoop => chunk_files(&files, reader.as_ref(), u64::MAX)?,
+        RunMode::Rayon => chunk_files(&files, reader.as_ref(), CHUNK_SIZE)?,
+    };
+    stats.discovery_time = discovery.elapsed();
+
+    // Readers send batches over a bounded channel so memory stays flat
+    // while the single database writer catches up
+    let row_numbers = table_metadata.metadata.row_numbers;
+    let file_stats = FileStatsCollector::default();
+    let progress = Progress::new(&table_metadata.metadata.name, &chunks, progress);
+    let (sender, receiver) = mpsc::sync_channel::<Result<Batch>>(CHANNEL_CAPACITY);
+    let inserted = thread::scope(|scope| {
+        let file_stats = &file_stats;
+        let progress = &progress;
+        scope.spawn(move || match mode {
+            RunMode::ForLoop => for_loop_loader::load(
+                chunks,
+                reader.as_ref(),
+                sender,
+                row_numbers,
+                file_stats,
+                progress,
+            ),
+            RunMode::Rayon => rayon_loader::load(
+                chunks,
+                reader.as_ref(),
+                sender,
+                row_numbers,
+                file_stats,
+                progress,
+            ),
+        });
+        load_db(connection, table_metadata, receiver, row_limit)
+    })?;
+    progress.finish();
+    (stats.rows_inserted, stats.rows_skipped, stats.insert_time) = inserted;
+    if stats.rows_skipped > 0 {
+        log::warn!(
+            "Skipped {} rows of {} that broke its column constraints",
+            stats.rows_skipped,
+            table_metadata.metadata.name
         );
     }
+    stats.index_time =
+        create_indexes(connection, table_metadata, &table_metadata.metadata.indexes)?;
+    stats.files = file_stats.into_files();
+    Ok(())
+}
+
+/// Read a chunk in batches, recording how long parsing took
+/// Time spent waiting for send isn't counted as parsing
+pub fn read_chunk(
+    chunk: &Chunk,
+    reader: &dyn FormatReader,
+    file_stats: &FileStatsCollector,
+    progress: &Progress,
+    mut send: impl FnMut(Batch) -> bool,
+) -> Result<()> {
+    let started = Instant::now();
+    let mut waiting = Duration::ZERO;
+    let mut rows: u64 = 0;
+    // Reading can stop before the end of the chunk, so only what was read counts
+    let bytes = Cell::new(0);
+    let result = read_chunk_batches(chunk, reader, BATCH_SIZE, &bytes, |batch| {
+        rows += batch.len() as u64;
+        progress.add_rows(batch.len() as u64);
+        let sending = Instant::now();
+        let sent = send(batch);
+        waiting += sending.elapsed();
+        sent
+    });
+    let bytes = bytes.get();
+    file_stats.record(
+        &chunk.path,
+        bytes,
+        rows,
+        started.elapsed().saturating_sub(waiting),
+    );
+    progress.chunk_done(chunk, bytes);
+    result
+}
+
+/// Count a table's rows straight from its files without loading them
+/// The count is the rows read in the returned statistics
+pub fn count_table(table_metadata: &TableMetadata, formats: &FormatRegistry) -> Result<TableStats> {
+    let started = Instant::now();
+    let mut stats = TableStats::new(&table_metadata.metadata.name, LoadStrategy::CountFromFiles);
+    let reader = get_reader(table_metadata, formats)?;
+    let files: Vec<PathBuf> = get_files(table_metadata)?
+        .into_iter()
+        .filter(|path| path.is_file() && reader.detect(path))
+        .collect();
+    stats.discovery_time = started.elapsed();
+
+    stats.files = files
+        .par_iter()
+        .map(|path| {
+            let counting = Instant::now();
+            let rows = reader.count(path)?;
+            Ok(FileStats {
+                path: path.clone(),
+                bytes: fs::metadata(path)?.len(),
+                rows,
+                parse_time: counting.elapsed(),
+            })
+        })
+        .collect::<Result<Vec<FileStats>>>()?;
+    stats.total_time = started.elapsed();
+    Ok(stats)
+}
+
+/// Get the reader for a table's format, set up with the table's format options
+pub fn get_reader(
+    table_metadata: &TableMetadata,
+    formats: &FormatRegistry,
+) -> Result<Arc<dyn FormatReader>> {
+    let metadata = &table_metadata.metadata;
+    if let (Format::Csv, Some(options)) = (&metadata.format, &metadata.options.csv) {
+        return Ok(Arc::new(CsvReader::new(options.clone())));
+    }
+    formats.get(metadata.format.name()).ok_or_else(|| {
+        WingsError::Metadata(format!(
+            "Unknown format {} for table {}",
+            table_metadata.metadata.format, table_metadata.metadata.name
+        ))
+    })
 }
 
-pub fn create_table_from_metadata(connection: &Connection, table_metadata: &TableMetadata) {
-    let create_table_sql = schema_to_db(&table_metadata);
-    connection.execute(create_table_sql).unwrap();
+/// Infer a table's schema from the first of its files the format reads
+pub fn infer_schema(
+    table_metadata: &TableMetadata,
+    formats: &FormatRegistry,
+) -> Result<BTreeMap<String, String>> {
+    let reader = get_reader(table_metadata, formats)?;
+    let path = get_files(table_metadata)?
+        .into_iter()
+        .find(|path| path.is_file() && reader.detect(path))
+        .ok_or_else(|| {
+            WingsError::Schema(format!(
+                "No {} files in {} to infer a schema from",
+                table_metadata.metadata.format,
+                table_metadata.metadata.data_path.display()
+            ))
+        })?;
+    reader.infer_schema(&path)
 }
 
-pub fn flatten(rows: Vec<Vec<BTreeMap<String, String>>>) -> Vec<BTreeMap<String, String>> {
-    let mut flat_rows = Vec::new();
-    for row in rows {
-        for part in row {
-            flat_rows.push(part);
+/// Check the columns a table declares are in a sample of its files
+/// Tables without files yet pass, since there's nothing to check against
+pub fn check_schema_against_files(
+    table_metadata: &TableMetadata,
+    formats: &FormatRegistry,
+) -> Result<()> {
+    let reader = get_reader(table_metadata, formats)?;
+    let Some(path) = get_files(table_metadata)
+        .unwrap_or_default()
+        .into_iter()
+        .find(|path| path.is_file() && reader.detect(path))
+    else {
+        return Ok(());
+    };
+    let sample = reader.infer_schema(&path)?;
+    let missing: Vec<&str> = table_metadata
+        .schema
+        .keys()
+        .filter(|column| !sample.contains_key(*column))
+        .map(String::as_str)
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let message = format!(
+        "Columns {} aren't in {}, it has {}",
+        missing.join(", "),
+        path.display(),
+        sample.keys().cloned().collect::<Vec<String>>().join(", ")
+    );
+    // JSON lines can leave a key out of the sampled lines and still have it later
+    if table_metadata.metadata.format == Format::Jsonl {
+        log::warn!("{}", message);
+        Ok(())
+    } else {
+        Err(WingsError::Schema(message))
+    }
+}
+
+/// Get every path under a table's data_path, sorted by file name
+/// A data_path with wildcards, like `data/*.csv`, gets the paths matching it
+pub fn get_files(table_metadata: &TableMetadata) -> Result<Vec<PathBuf>> {
+    let data_path = &table_metadata.metadata.data_path;
+    let pattern = data_path.to_string_lossy();
+    if !has_wildcard(&pattern) {
+        let mut files: Vec<PathBuf> = Vec::new();
+        for entry in WalkDir::new(data_path).sort_by_file_name() {
+            files.push(entry?.path().to_path_buf());
+        }
+        return Ok(files);
+    }
+
+    // Walk from the last directory before a wildcard
+    let base: PathBuf = data_path
+        .components()
+        .take_while(|component| !has_wildcard(&component.as_os_str().to_string_lossy()))
+        .collect();
+    let walk_root = if base.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        base.as_path()
+    };
+    let mut files: Vec<PathBuf> = Vec::new();
+    for entry in WalkDir::new(walk_root).sort_by_file_name() {
+        let path = entry?.into_path();
+        // Paths under the current directory are walked as ./path
+        let relative = if base.as_os_str().is_empty() {
+            path.strip_prefix(".").unwrap_or(&path)
+        } else {
+            &path
+        };
+        if glob_match(&pattern, &relative.to_string_lossy()) {
+            files.push(path);
         }
     }
-    return flat_rows;
+    Ok(files)
+}
+
+/// Attach an in-memory database for a table's namespace, unless it has no
+/// namespace or the database is already attached
+pub fn attach_namespace(connection: &Connection, table: &str) -> Result<()> {
+    let (Some(namespace), _) = split_table_name(table) else {
+        return Ok(());
+    };
+    let mut statement = connection.prepare("select 1 from pragma_database_list where name = ?")?;
+    statement.bind((1, namespace))?;
+    if statement.next()? == State::Done {
+        connection.execute(format!(
+            "attach database ':memory:' as {}",
+            quote_identifier(namespace)
+        ))?;
+    }
+    Ok(())
+}
+
+pub fn create_table_from_metadata(
+    connection: &Connection,
+    table_metadata: &TableMetadata,
+) -> Result<()> {
+    attach_namespace(connection, &table_metadata.metadata.name)?;
+    let create_table_sql = schema_to_db(table_metadata);
+    connection.execute(create_table_sql)?;
+    Ok(())
+}
+
+/// Build indexes on a loaded table, returning the time it took
+/// Indexes that are already built are left as they are
+pub fn create_indexes(
+    connection: &Connection,
+    table_metadata: &TableMetadata,
+    indexes: &[Index],
+) -> Result<Duration> {
+    let started = Instant::now();
+    for index in indexes {
+        connection
+            .execute(index.to_sql(&table_metadata.metadata.name))
+            .map_err(|error| match error.code {
+                Some(SQLITE_CONSTRAINT) => WingsError::Schema(format!(
+                    "Can't build unique index {} on {}: {}",
+                    index,
+                    table_metadata.metadata.name,
+                    error.message.unwrap_or_default()
+                )),
+                _ => error.into(),
+            })?;
+    }
+    Ok(started.elapsed())
 }
 
+/// Number each row in the batch in the `_row` column, continuing from row_number
+pub fn number_rows(batch: &mut Batch, row_number: &mut u64) {
+    for row in batch.iter_mut() {
+        *row_number += 1;
+        row.insert(ROW_NUMBER_COLUMN.to_string(), row_number.to_string());
+    }
+}
+
+/// Insert batches from the receiver until every reader is done or the row
+/// limit is reached
+/// Returns the number of rows inserted, the number skipped for breaking a
+/// column constraint, and the time spent inserting
 pub fn load_db(
     connection: &Connection,
-    table_name: &String,
-    columns: Vec<String>,
-    values: Vec<String>,
-    rows: Vec<BTreeMap<String, String>>,
-) {
-    let columns_clause = columns.join(",");
-    let values_clause = values.join(",");
+    table_metadata: &TableMetadata,
+    batches: Receiver<Result<Batch>>,
+    row_limit: Option<u64>,
+) -> Result<(u64, u64, Duration)> {
+    // Either specify fields in insert or make sure values are in the right order
+    let columns: Vec<String> = table_metadata.columns();
+    // Values are bound by position since column names needn't be valid
+    // parameter names
+    let columns_clause: Vec<String> = columns
+        .iter()
+        .map(|column| quote_identifier(column))
+        .collect();
+    let values_clause: Vec<String> = columns
+        .iter()
+        .enumerate()
+        .map(|(index, column)| {
+            // A missing value would be bound as NULL, which overrides the default
+            match table_metadata
+                .constraints
+                .get(column)
+                .and_then(|constraints| constraints.default.as_ref())
+            {
+                Some(default) => format!("coalesce(?{}, {})", index + 1, default.to_sql()),
+                None => format!("?{}", index + 1),
+            }
+        })
+        .collect();
     let query = format!(
         "INSERT INTO {} ({}) VALUES ({})",
-        table_name, columns_clause, values_clause
+        quote_table_name(&table_metadata.metadata.name),
+        columns_clause.join(","),
+        values_clause.join(",")
     );
 
-    for row in rows {
-        let mut statement = connection.prepare(query.clone()).unwrap();
-        // For each row create a vector of tuples that is
-        // (":column", "value")
+    let mut statement = connection.prepare(query)?;
+    let mut inserted = Inserted::default();
+    let mut insert_time = Duration::ZERO;
+    // Returning early drops the receiver, which stops the readers
+    for batch in batches {
+        let mut batch = batch?;
+        if let Some(limit) = row_limit {
+            batch.truncate((limit - inserted.rows).try_into().unwrap_or(usize::MAX));
+        }
+        let inserting = Instant::now();
+        connection.execute("BEGIN")?;
+        match insert_batch(
+            &mut statement,
+            table_metadata,
+            &columns,
+            batch,
+            &mut inserted,
+        ) {
+            Ok(()) => {
+                connection.execute("COMMIT")?;
+                insert_time += inserting.elapsed();
+                if row_limit.is_some_and(|limit| inserted.rows >= limit) {
+                    break;
+                }
+            }
+            Err(error) => {
+                connection.execute("ROLLBACK")?;
+                return Err(error);
+            }
+        }
+    }
+    Ok((inserted.rows, inserted.skipped, insert_time))
+}
+
+/// Rows inserted and skipped so far
+#[derive(Default)]
+struct Inserted {
+    rows: u64,
+    skipped: u64,
+}
 
-        let mut index = 0;
-        let bind_vars: Vec<(&str, Value)> = columns
+fn insert_batch(
+    statement: &mut Statement,
+    table_metadata: &TableMetadata,
+    columns: &[String],
+    batch: Batch,
+    inserted: &mut Inserted,
+) -> Result<()> {
+    for row in batch {
+        // For each row create a vector of tuples that is
+        // (position, "value")
+        // Columns missing from the row, like JSON nulls, are NULL
+        let bind_vars: Vec<(usize, Value)> = columns
             .iter()
-            .map(|f| {
-                let bind_var: (&str, Value) =
-                    (values[index].as_str(), row.get(f).unwrap().as_str().into());
-                index = index + 1;
-                return bind_var;
+            .enumerate()
+            .map(|(index, column)| match row.get(column) {
+                Some(cell) => (index + 1, cell.as_str().into()),
+                None => (index + 1, Value::Null),
             })
             .collect();
-        let res = statement.bind_iter::<_, (_, Value)>(bind_vars);
-        match res {
-            Ok(_) => (),
-            Err(e) => println!("There was an error inserting data. {}", e),
+        statement.reset()?;
+        statement.bind_iter::<_, (_, Value)>(bind_vars)?;
+        match statement.next() {
+            Ok(_) => inserted.rows += 1,
+            Err(error) if error.code == Some(SQLITE_CONSTRAINT) => {
+                match table_metadata.metadata.on_error {
+                    ErrorPolicy::Skip => {
+                        inserted.skipped += 1;
+                        // Resetting after a failed step returns that step's
+                        // error again, so it's cleared here rather than failing
+                        // the next row
+                        let _ = statement.reset();
+                    }
+                    ErrorPolicy::Fail => {
+                        return Err(WingsError::Schema(format!(
+                            "Row of {} breaks a column constraint: {}",
+                            table_metadata.metadata.name,
+                            error.message.unwrap_or_default()
+                        )))
+                    }
+                }
+            }
+            Err(error) => return Err(error.into()),
         }
-
-        let _ = statement.next();
     }
+    Ok(())
 }
 
 #[allow(dead_code)]
@@ -126,5 +551,5 @@ fn project(
     #[allow(unused_variables)] schema: BTreeMap<String, String>,
 ) -> BTreeMap<String, String> {
     // TODO
-    return row;
+    row
 }
@@ -1,5 +1,5 @@
-use std::collections::{HashMap, HashSet};
 use pad::PadStr;
+use std::collections::{HashMap, HashSet};
 
 const DIVIDER: &str = "|";
 
@@ -22,22 +22,26 @@ impl DisplayRow {
             // value_widths.insert(row_value.clone(), row_value.len()); // Change this to be column -> size of value
         });
 
-        return DisplayRow {
+        DisplayRow {
             raw_row: row,
             // value_widths,
             columns,
             // column_widths
-        };
+        }
     }
 }
 
 /// Render display lines in result table
 pub fn render(display_rows: Vec<DisplayRow>) {
+    if display_rows.is_empty() {
+        println!("No rows returned");
+        return;
+    }
     let columns = get_column_row(&display_rows);
     let max_widths = get_max_widths(&display_rows, &columns);
 
     let mut total_width: usize = max_widths.values().sum();
-    total_width = total_width + (columns.len() - 1);
+    total_width += columns.len() - 1;
 
     let first_line = get_first_display_line(total_width);
     let column_row = get_column_display_line(&columns, &max_widths);
@@ -65,7 +69,7 @@ fn get_data_display_lines(
     for drow in display_rows {
         let mut results_row_vec: Vec<String> = vec![DIVIDER.to_string()];
         for column in &columns {
-            let cell_width = max_widths.get(column).unwrap().clone();
+            let cell_width = *max_widths.get(column).unwrap();
             let value_default = String::from(""); // Make this a constant
             let content = drow.raw_row.get(column).unwrap_or(&value_default);
             let cell = content.pad_to_width(cell_width).clone();
@@ -75,8 +79,7 @@ fn get_data_display_lines(
         let result_row = results_row_vec.into_iter().collect::<String>();
         result_rows.push(result_row + "\n");
     }
-    let render_rows = result_rows.into_iter().collect::<String>();
-    render_rows
+    result_rows.into_iter().collect::<String>()
 }
 
 /// Format column display line with dividers between values
@@ -86,13 +89,12 @@ fn get_column_display_line(
 ) -> String {
     let mut column_row_vec: Vec<String> = vec![DIVIDER.to_string()];
     columns.iter().for_each(|column| {
-        let cell_width = max_widths.get(column).unwrap().clone();
+        let cell_width = *max_widths.get(column).unwrap();
         let cell = column.pad_to_width(cell_width).clone();
         column_row_vec.push(cell);
         column_row_vec.push(DIVIDER.to_string());
     });
-    let column_row = column_row_vec.into_iter().collect::<String>();
-    column_row
+    column_row_vec.into_iter().collect::<String>()
 }
 
 /// Get result table border line
@@ -102,13 +104,12 @@ fn get_first_display_line(total_width: usize) -> String {
     let first_line_end = vec!["+"];
     first_line_vec.extend_from_slice(&first_line_border);
     first_line_vec.extend_from_slice(&first_line_end);
-    let first_line = first_line_vec.into_iter().collect::<String>();
-    first_line
+    first_line_vec.into_iter().collect::<String>()
 }
 
 /// Get set of columns based on display rows
 /// Each display row isn't gaurenteed to have the same columns so all have to be checked
-fn get_column_row(display_rows: &Vec<DisplayRow>) -> HashSet<String> {
+fn get_column_row(display_rows: &[DisplayRow]) -> HashSet<String> {
     let mut columns: HashSet<String> = HashSet::new();
     display_rows.iter().for_each(|drow| {
         drow.columns.iter().for_each(|col| {
@@ -116,13 +117,13 @@ fn get_column_row(display_rows: &Vec<DisplayRow>) -> HashSet<String> {
         })
     });
 
-    return columns;
+    columns
 }
 
 /// Get the maximum widths of each column's values
 /// Including column name
 fn get_max_widths(
-    display_rows: &Vec<DisplayRow>,
+    display_rows: &[DisplayRow],
     columns: &HashSet<String>,
 ) -> HashMap<String, usize> {
     let mut max_widths: HashMap<String, usize> = HashMap::new();
@@ -136,10 +137,10 @@ fn get_max_widths(
             let column_width = drow.raw_row.get(drow_column).unwrap().len();
             if max_widths.contains_key(drow_column) {
                 if max_widths.get(drow_column).unwrap() < &column_width {
-                    max_widths.insert(drow_column.clone(), column_width.clone());
+                    max_widths.insert(drow_column.clone(), column_width);
                 }
             } else {
-                max_widths.insert(drow_column.clone(), column_width.clone());
+                max_widths.insert(drow_column.clone(), column_width);
             }
         });
     });
@@ -149,8 +150,9 @@ fn get_max_widths(
 
 /// Generic function to convert vector of hashmaps to DisplayRows
 pub fn display_rows_from_maps(maps: Vec<HashMap<String, String>>) -> Vec<DisplayRow> {
-    let display_rows: Vec<DisplayRow> = maps.iter().map(|map| {
-        DisplayRow::new(map.clone())
-    }).collect();
-    return display_rows;
+    let display_rows: Vec<DisplayRow> = maps
+        .iter()
+        .map(|map| DisplayRow::new(map.clone()))
+        .collect();
+    display_rows
 }
@@ -0,0 +1,87 @@
+use std::{fmt, io};
+
+/// Errors returned by wings
+#[derive(Debug)]
+pub enum WingsError {
+    /// Catalog problems such as a name that's taken or an uninitialized program directory
+    Metadata(String),
+    /// A table or view doesn't exist
+    NotFound(String),
+    /// Reading or writing files failed
+    Io(io::Error),
+    /// A table definition or data file couldn't be parsed
+    Parse(String),
+    /// Data doesn't match the table schema
+    Schema(String),
+    /// The database rejected a statement
+    Sql(sqlite::Error),
+    /// A client sent a message the server can't read
+    Protocol(String),
+}
+
+pub type Result<T> = std::result::Result<T, WingsError>;
+
+impl fmt::Display for WingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WingsError::Metadata(message) | WingsError::NotFound(message) => {
+                write!(f, "{}", message)
+            }
+            WingsError::Io(error) => write!(f, "IO error: {}", error),
+            WingsError::Parse(message) => write!(f, "Parse error: {}", message),
+            WingsError::Schema(message) => write!(f, "Schema error: {}", message),
+            WingsError::Sql(error) => write!(f, "SQL error: {}", error),
+            WingsError::Protocol(message) => write!(f, "Protocol error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for WingsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WingsError::Io(error) => Some(error),
+            WingsError::Sql(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WingsError {
+    fn from(error: io::Error) -> Self {
+        WingsError::Io(error)
+    }
+}
+
+impl From<sqlite::Error> for WingsError {
+    fn from(error: sqlite::Error) -> Self {
+        WingsError::Sql(error)
+    }
+}
+
+impl From<serde_yaml::Error> for WingsError {
+    fn from(error: serde_yaml::Error) -> Self {
+        WingsError::Parse(error.to_string())
+    }
+}
+
+impl From<csv::Error> for WingsError {
+    fn from(error: csv::Error) -> Self {
+        if error.is_io_error() {
+            WingsError::Io(error.into())
+        } else {
+            WingsError::Parse(error.to_string())
+        }
+    }
+}
+
+impl From<apache_avro::Error> for WingsError {
+    fn from(error: apache_avro::Error) -> Self {
+        WingsError::Parse(error.to_string())
+    }
+}
+
+impl From<walkdir::Error> for WingsError {
+    fn from(error: walkdir::Error) -> Self {
+        WingsError::Io(error.into())
+    }
+}
@@ -0,0 +1,90 @@
+use std::{fmt, path::PathBuf};
+
+/// Number of files listed for each table before the rest are summarized,
+/// in plans and in statistics
+pub const LISTED_FILES: usize = 10;
+
+/// How a table would be read to answer a query
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoadStrategy {
+    /// Already loaded in the session, nothing is read
+    Cached,
+    /// Records are counted in the files without loading them
+    CountFromFiles,
+    /// Reading stops once this many rows are loaded
+    Limit(u64),
+    /// Every file is read and loaded
+    Full,
+}
+
+impl fmt::Display for LoadStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadStrategy::Cached => write!(f, "cached in session, no files read"),
+            LoadStrategy::CountFromFiles => write!(f, "count records in files, not loaded"),
+            LoadStrategy::Limit(limit) => write!(f, "stop after {} rows", limit),
+            LoadStrategy::Full => write!(f, "full scan"),
+        }
+    }
+}
+
+/// What would be read for one table
+#[derive(Debug, Clone)]
+pub struct TablePlan {
+    pub name: String,
+    pub format: String,
+    pub data_path: PathBuf,
+    /// Files in the table's format that would be read
+    pub files: Vec<PathBuf>,
+    /// Total size of the files
+    pub bytes: u64,
+    /// Columns loaded from the files
+    pub columns: Vec<String>,
+    pub strategy: LoadStrategy,
+}
+
+/// Load strategy for each table in a query and SQLite's plan for running it
+#[derive(Debug, Clone)]
+pub struct QueryPlan {
+    pub tables: Vec<TablePlan>,
+    /// Lines of `EXPLAIN QUERY PLAN`, indented by depth
+    pub sqlite_plan: Vec<String>,
+}
+
+impl fmt::Display for TablePlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.name)?;
+        writeln!(f, "  Format: {}", self.format)?;
+        writeln!(f, "  Path: {}", self.data_path.display())?;
+        writeln!(f, "  Files: {} ({} bytes)", self.files.len(), self.bytes)?;
+        for file in self.files.iter().take(LISTED_FILES) {
+            writeln!(f, "    {}", file.display())?;
+        }
+        if self.files.len() > LISTED_FILES {
+            writeln!(f, "    ... {} more", self.files.len() - LISTED_FILES)?;
+        }
+        writeln!(
+            f,
+            "  Columns: all {} ({}), projection is not pushed down",
+            self.columns.len(),
+            self.columns.join(", ")
+        )?;
+        writeln!(f, "  Predicates pushed down: none")?;
+        writeln!(f, "  Partitions pruned: none")?;
+        write!(f, "  Load: {}", self.strategy)
+    }
+}
+
+impl fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Tables\n--------------------")?;
+        for table in &self.tables {
+            writeln!(f, "{}", table)?;
+        }
+        write!(f, "--------------------\nSQLite plan")?;
+        for line in &self.sqlite_plan {
+            write!(f, "\n{}", line)?;
+        }
+        Ok(())
+    }
+}
@@ -1,20 +1,37 @@
-use std::{collections::BTreeMap, path::PathBuf};
+use std::sync::mpsc::SyncSender;
 
-use crate::utils::read_path;
+use crate::data_loader::{number_rows, read_chunk, Batch};
+use crate::error::Result;
+use crate::formats::{Chunk, FormatReader};
+use crate::progress::Progress;
+use crate::stats::FileStatsCollector;
 
-pub fn load(files: Vec<PathBuf>, format: String) -> Vec<Vec<BTreeMap<String, String>>> {
-    let for_rows: Vec<Vec<BTreeMap<String, String>>> = files
-        .iter()
-        .map(|entry| {
-            let raw_rows = read_path(entry.as_path(), &format);
-            match raw_rows {
-                Ok(ok_rows) => {
-                    // Add projection function here
-                    return ok_rows;
-                }
-                Err(_) => panic!("Cannot read data rows"),
+pub fn load(
+    chunks: Vec<Chunk>,
+    reader: &dyn FormatReader,
+    sender: SyncSender<Result<Batch>>,
+    row_numbers: bool,
+    file_stats: &FileStatsCollector,
+    progress: &Progress,
+) {
+    let mut row_number: u64 = 0;
+    for chunk in chunks {
+        let mut closed = false;
+        // Add projection function here
+        let result = read_chunk(&chunk, reader, file_stats, progress, |mut batch| {
+            if row_numbers {
+                number_rows(&mut batch, &mut row_number);
             }
-        })
-        .collect();
-    return for_rows;
+            closed = sender.send(Ok(batch)).is_err();
+            !closed
+        });
+        if let Err(error) = result {
+            let _ = sender.send(Err(error));
+            return;
+        }
+        // The writer hangs up once it has every row it needs
+        if closed {
+            return;
+        }
+    }
 }
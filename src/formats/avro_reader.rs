@@ -0,0 +1,267 @@
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufReader, Read, Seek},
+    path::Path,
+};
+
+use apache_avro::{from_value, schema::Schema, Reader};
+
+use crate::error::{Result, WingsError};
+use crate::formats::{has_extension, open_chunk, record_from_json, Chunk, FormatReader, Records};
+
+/// Avro object container files
+pub struct AvroReader;
+
+impl FormatReader for AvroReader {
+    fn name(&self) -> &str {
+        "avro"
+    }
+
+    fn detect(&self, path: &Path) -> bool {
+        has_extension(path, "avro")
+    }
+
+    fn infer_schema(&self, path: &Path) -> Result<BTreeMap<String, String>> {
+        let f = File::open(path)?;
+        let r = Reader::new(f).map_err(|error| decode_error(error, path))?;
+        match r.writer_schema() {
+            Schema::Record(record) => Ok(record
+                .fields
+                .iter()
+                .map(|field| (field.name.clone(), column_type(&field.schema).to_string()))
+                .collect()),
+            _ => Err(WingsError::Parse(format!(
+                "Avro file {} does not contain records",
+                path.display()
+            ))),
+        }
+    }
+
+    fn count(&self, path: &Path) -> Result<u64> {
+        // Loading leaves out records whose fields are all null, which only
+        // reading the records can tell when the schema allows them
+        if records_can_be_empty(path)? {
+            let mut count = 0;
+            for row in self.read(path)? {
+                if !row?.is_empty() {
+                    count += 1;
+                }
+            }
+            return Ok(count);
+        }
+        let len = fs::metadata(path)?.len();
+        count_blocks(BufReader::new(File::open(path)?), len, path)
+    }
+
+    fn read<'a>(&self, path: &'a Path) -> Result<Records<'a>> {
+        records_from(File::open(path)?, path)
+    }
+
+    /// Avro files aren't split, so chunks are whole files
+    fn read_chunk<'a>(&self, chunk: &'a Chunk, bytes: &'a Cell<u64>) -> Result<Records<'a>> {
+        records_from(open_chunk(chunk, bytes)?, &chunk.path)
+    }
+}
+
+fn records_from<'a, R: Read + 'a>(reader: R, path: &'a Path) -> Result<Records<'a>> {
+    let r = Reader::new(reader).map_err(|error| decode_error(error, path))?;
+    let records = r.map(move |value| {
+        let value = value.map_err(|error| decode_error(error, path))?;
+        let rec =
+            from_value::<serde_json::Value>(&value).map_err(|error| decode_error(error, path))?;
+        match rec.as_object() {
+            Some(object_row) => Ok(record_from_json(object_row)),
+            None => Err(WingsError::Parse(format!(
+                "Avro file {} does not contain records",
+                path.display()
+            ))),
+        }
+    });
+    Ok(Box::new(records))
+}
+
+fn decode_error(error: apache_avro::Error, path: &Path) -> WingsError {
+    WingsError::Parse(format!("{} in {}", error, path.display()))
+}
+
+/// Magic bytes at the start of an Avro object container file
+const AVRO_MAGIC: [u8; 4] = [b'O', b'b', b'j', 1];
+
+/// Length of the sync marker after the header and each block
+const SYNC_MARKER_SIZE: i64 = 16;
+
+/// Whether every field of the file's records can be null
+fn records_can_be_empty(path: &Path) -> Result<bool> {
+    let r = Reader::new(File::open(path)?).map_err(|error| decode_error(error, path))?;
+    Ok(match r.writer_schema() {
+        Schema::Record(record) => record.fields.iter().all(|field| match &field.schema {
+            Schema::Null => true,
+            Schema::Union(union) => union.is_nullable(),
+            _ => false,
+        }),
+        _ => false,
+    })
+}
+
+/// Count records by adding up the object counts in each block header,
+/// skipping over the block data without decoding it
+/// Blocks have to fit in the file's len bytes, since reading past the end
+/// doesn't fail on its own
+fn count_blocks<R: Read + Seek>(mut reader: BufReader<R>, len: u64, path: &Path) -> Result<u64> {
+    let truncated = || {
+        WingsError::Parse(format!(
+            "Avro file {} is truncated or corrupt",
+            path.display()
+        ))
+    };
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != AVRO_MAGIC {
+        return Err(WingsError::Parse(format!(
+            "{} is not an Avro container file",
+            path.display()
+        )));
+    }
+
+    // File metadata is a map from string to bytes, written in blocks
+    loop {
+        let mut entries = read_long(&mut reader)?.unwrap_or(0);
+        if entries == 0 {
+            break;
+        }
+        if entries < 0 {
+            // Negative counts are followed by the block size in bytes
+            entries = -entries;
+            read_long(&mut reader)?;
+        }
+        for _ in 0..entries * 2 {
+            let length = read_long(&mut reader)?.unwrap_or(0);
+            reader.seek_relative(length)?;
+        }
+    }
+    reader.seek_relative(SYNC_MARKER_SIZE)?;
+    if reader.stream_position()? > len {
+        return Err(truncated());
+    }
+
+    let mut count: u64 = 0;
+    while let Some(objects) = read_long(&mut reader)? {
+        let size = read_long(&mut reader)?.ok_or_else(truncated)?;
+        let remaining = len.saturating_sub(reader.stream_position()?);
+        if objects < 0 || size < 0 || size.saturating_add(SYNC_MARKER_SIZE) as u64 > remaining {
+            return Err(truncated());
+        }
+        reader.seek_relative(size + SYNC_MARKER_SIZE)?;
+        count += objects as u64;
+    }
+    Ok(count)
+}
+
+/// Read a zig-zag encoded variable length long, or None at the end of the file
+fn read_long<R: Read>(reader: &mut R) -> Result<Option<i64>> {
+    let mut value: u64 = 0;
+    let mut shift = 0;
+    let mut byte = [0; 1];
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            if shift == 0 {
+                return Ok(None);
+            }
+            return Err(WingsError::Parse(
+                "Avro file ends in the middle of a number".to_string(),
+            ));
+        }
+        value |= u64::from(byte[0] & 0x7f) << shift;
+        if byte[0] & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+        if shift > 63 {
+            return Err(WingsError::Parse("Avro number is too long".to_string()));
+        }
+    }
+    Ok(Some((value >> 1) as i64 ^ -((value & 1) as i64)))
+}
+
+fn column_type(schema: &Schema) -> &'static str {
+    match schema {
+        Schema::Boolean | Schema::Int | Schema::Long => "int",
+        Schema::Float | Schema::Double => "real",
+        Schema::Union(union) => union
+            .variants()
+            .iter()
+            .find(|variant| **variant != Schema::Null)
+            .map(column_type)
+            .unwrap_or("text"),
+        _ => "text",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::Path};
+
+    use apache_avro::{schema::Schema, types::Record, Writer};
+
+    use crate::error::WingsError;
+    use crate::formats::{read_path, AvroReader, FormatReader};
+
+    #[test]
+    fn corrupt_file_fails() {
+        let data = fs::read("examples/avro_data/twitter.avro").unwrap();
+        let path = env::temp_dir().join(format!("wings_corrupt_{}.avro", std::process::id()));
+        fs::write(&path, &data[..data.len() - 30]).unwrap();
+        let rows: Vec<_> = AvroReader.read(&path).unwrap().collect();
+        fs::remove_file(&path).unwrap();
+
+        // Rows aren't silently dropped, the error names the file
+        assert!(
+            matches!(rows.last(), Some(Err(WingsError::Parse(message))) if message.contains("wings_corrupt"))
+        );
+    }
+
+    #[test]
+    fn count_truncated_file_fails() {
+        let data = fs::read("examples/avro_data/twitter.avro").unwrap();
+        let path = env::temp_dir().join(format!("wings_truncated_{}.avro", std::process::id()));
+        fs::write(&path, &data[..data.len() - 30]).unwrap();
+        let count = AvroReader.count(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(matches!(count, Err(WingsError::Parse(_))));
+        assert_eq!(
+            AvroReader
+                .count(Path::new("examples/avro_data/twitter.avro"))
+                .unwrap(),
+            2
+        );
+    }
+
+    #[test]
+    fn count_what_loading_keeps() {
+        let schema = Schema::parse_str(
+            r#"{"type": "record", "name": "event", "fields": [
+                {"name": "level", "type": ["null", "string"]},
+                {"name": "code", "type": ["null", "long"]}
+            ]}"#,
+        )
+        .unwrap();
+        let mut writer = Writer::new(&schema, Vec::new());
+        for (level, code) in [(Some("error"), Some(500)), (None, None), (None, Some(200))] {
+            let mut record = Record::new(&schema).unwrap();
+            record.put("level", level.map(str::to_string));
+            record.put("code", code.map(|code: i64| code));
+            writer.append(record).unwrap();
+        }
+        let path = env::temp_dir().join(format!("wings_nulls_{}.avro", std::process::id()));
+        fs::write(&path, writer.into_inner().unwrap()).unwrap();
+        let count = AvroReader.count(&path);
+        let loaded = read_path(&path, &AvroReader).unwrap().len();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, 2);
+        assert_eq!(count.unwrap(), 2);
+    }
+}
@@ -0,0 +1,460 @@
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufReader, Read, Seek, SeekFrom},
+    ops::Range,
+    path::Path,
+};
+
+use csv::{ByteRecord, ReaderBuilder, StringRecord};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Result, WingsError};
+use crate::formats::{has_extension, open_chunk, Chunk, FormatReader, Record, Records};
+
+/// Number of records sampled when inferring column types
+const INFER_SAMPLE_SIZE: usize = 100;
+
+/// Comma separated files with a header row, or as set by [`CsvOptions`]
+#[derive(Default)]
+pub struct CsvReader {
+    options: CsvOptions,
+}
+
+/// How a table's CSV files are laid out
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CsvOptions {
+    pub delimiter: char,
+    pub quote: char,
+    /// Without headers columns are named column_1, column_2, ...
+    pub has_headers: bool,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            delimiter: ',',
+            quote: '"',
+            has_headers: true,
+        }
+    }
+}
+
+impl CsvOptions {
+    /// Check the delimiter and quote are single byte characters
+    pub fn validate(&self) -> Result<()> {
+        for (name, c) in [("delimiter", self.delimiter), ("quote", self.quote)] {
+            if !c.is_ascii() {
+                return Err(WingsError::Metadata(format!(
+                    "CSV {} must be a single byte character, not {}",
+                    name, c
+                )));
+            }
+        }
+        if self.delimiter == self.quote {
+            return Err(WingsError::Metadata(
+                "CSV delimiter and quote must be different".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl CsvReader {
+    pub fn new(options: CsvOptions) -> CsvReader {
+        CsvReader { options }
+    }
+
+    fn builder(&self) -> ReaderBuilder {
+        let mut builder = ReaderBuilder::new();
+        builder
+            .delimiter(self.options.delimiter as u8)
+            .quote(self.options.quote as u8)
+            .has_headers(self.options.has_headers);
+        builder
+    }
+
+    /// Header row of the file at path, None when the file has no headers
+    fn headers(&self, path: &Path) -> Result<Option<StringRecord>> {
+        if !self.options.has_headers {
+            return Ok(None);
+        }
+        Ok(Some(self.builder().from_path(path)?.headers()?.clone()))
+    }
+}
+
+impl FormatReader for CsvReader {
+    fn name(&self) -> &str {
+        "csv"
+    }
+
+    fn detect(&self, path: &Path) -> bool {
+        has_extension(path, "csv")
+    }
+
+    fn infer_schema(&self, path: &Path) -> Result<BTreeMap<String, String>> {
+        let headers = self.headers(path)?;
+        let mut rdr = self.builder().from_path(path)?;
+
+        // Narrow each column from int to real to text as values are seen
+        let mut types: Vec<&str> = match &headers {
+            Some(headers) => vec!["int"; headers.len()],
+            None => Vec::new(),
+        };
+        for result in rdr.records().take(INFER_SAMPLE_SIZE) {
+            let record = result?;
+            if headers.is_none() && types.len() < record.len() {
+                types.resize(record.len(), "int");
+            }
+            for (index, value) in record.iter().enumerate().take(types.len()) {
+                types[index] = narrow_type(types[index], value);
+            }
+        }
+
+        Ok(types
+            .into_iter()
+            .enumerate()
+            .map(|(index, column_type)| {
+                (
+                    column_name(headers.as_ref(), index),
+                    column_type.to_string(),
+                )
+            })
+            .collect())
+    }
+
+    fn read<'a>(&self, path: &'a Path) -> Result<Records<'a>> {
+        let headers = self.headers(path)?;
+        let rdr = self.builder().from_path(path)?;
+        Ok(records_with_headers(rdr, headers))
+    }
+
+    fn count(&self, path: &Path) -> Result<u64> {
+        let mut rdr = self.builder().from_path(path)?;
+        let mut record = ByteRecord::new();
+        let mut count = 0;
+        while rdr.read_byte_record(&mut record)? {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn split(&self, path: &Path, chunk_size: u64) -> Result<Option<Vec<Range<u64>>>> {
+        let len = fs::metadata(path)?.len();
+        if len <= chunk_size {
+            return Ok(None);
+        }
+        record_ranges(
+            File::open(path)?,
+            len,
+            chunk_size,
+            self.options.delimiter as u8,
+            self.options.quote as u8,
+            self.options.has_headers,
+        )
+    }
+
+    fn read_range<'a>(&self, path: &'a Path, range: Range<u64>) -> Result<Records<'a>> {
+        let headers = self.headers(path)?;
+
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(range.start))?;
+        let rdr = self
+            .builder()
+            .has_headers(false)
+            .from_reader(file.take(range.end - range.start));
+        Ok(records_with_headers(rdr, headers))
+    }
+
+    fn read_chunk<'a>(&self, chunk: &'a Chunk, bytes: &'a Cell<u64>) -> Result<Records<'a>> {
+        let headers = self.headers(&chunk.path)?;
+        let mut builder = self.builder();
+        // Ranges from split start after the header row
+        if chunk.range.is_some() {
+            builder.has_headers(false);
+        }
+        let rdr = builder.from_reader(open_chunk(chunk, bytes)?);
+        Ok(records_with_headers(rdr, headers))
+    }
+}
+
+/// Name of the column at index, column_1, column_2, ... without headers
+fn column_name(headers: Option<&StringRecord>, index: usize) -> String {
+    match headers {
+        Some(headers) => headers.get(index).unwrap_or_default().to_string(),
+        None => format!("column_{}", index + 1),
+    }
+}
+
+/// Iterate over each record (row) in the CSV file
+fn records_with_headers<'a, R: Read + 'a>(
+    rdr: csv::Reader<R>,
+    headers: Option<StringRecord>,
+) -> Records<'a> {
+    let records = rdr.into_records().map(move |result| {
+        let record = result?;
+        let mut row: Record = BTreeMap::new();
+        for (index, value) in record.iter().enumerate() {
+            match &headers {
+                Some(headers) => {
+                    if let Some(key) = headers.get(index) {
+                        row.insert(key.to_string(), value.to_string());
+                    }
+                }
+                None => {
+                    row.insert(column_name(None, index), value.to_string());
+                }
+            }
+        }
+        Ok(row)
+    });
+    Box::new(records)
+}
+
+/// Bytes scanned past a split point for a quote that shows whether the
+/// split point is inside a quoted field
+const RESYNC_WINDOW: u64 = 1024 * 1024;
+
+/// Find byte ranges after the header row that end on a record boundary
+/// Each boundary is found by scanning forward from its split point, so the
+/// file isn't read as a whole. None when the quoting near a split point
+/// doesn't show where a record starts, and the file is read as one range
+fn record_ranges(
+    file: File,
+    len: u64,
+    chunk_size: u64,
+    delimiter: u8,
+    quote: u8,
+    has_headers: bool,
+) -> Result<Option<Vec<Range<u64>>>> {
+    let mut reader = BufReader::new(file);
+    let mut ranges: Vec<Range<u64>> = Vec::new();
+    // Without a header row the first chunk starts at the beginning
+    let mut start = if has_headers {
+        match next_record_start(&mut reader, 0, len, delimiter, quote)? {
+            Some(start) => start,
+            None => return Ok(None),
+        }
+    } else {
+        0
+    };
+    while start < len {
+        let target = start + chunk_size;
+        if target >= len {
+            ranges.push(start..len);
+            break;
+        }
+        let Some(end) = next_record_start(&mut reader, target, len, delimiter, quote)? else {
+            return Ok(None);
+        };
+        ranges.push(start..end);
+        start = end;
+    }
+    Ok(Some(ranges))
+}
+
+/// Offset of the first record starting after `offset`, or the file length
+/// when there isn't one
+///
+/// Newlines are only record boundaries outside quotes. Counting quotes from
+/// `offset` gives the quote state relative to the state at `offset`, which is
+/// worked out from the first quote whose neighbours show it opens a field
+/// (followed by field content) or closes one (between content and a
+/// delimiter or newline). A window without any quotes is taken to be
+/// unquoted. None when the window has quotes but none of them tell.
+fn next_record_start(
+    reader: &mut BufReader<File>,
+    offset: u64,
+    len: u64,
+    delimiter: u8,
+    quote: u8,
+) -> Result<Option<u64>> {
+    let is_content =
+        |byte: u8| byte != delimiter && byte != quote && byte != b'\n' && byte != b'\r';
+    reader.seek(SeekFrom::Start(offset.saturating_sub(1)))?;
+    let mut bytes = reader.bytes();
+    // The beginning of the file is outside quotes
+    let mut started_in_quotes = if offset == 0 { Some(false) } else { None };
+    let mut previous = if offset == 0 {
+        None
+    } else {
+        bytes.next().transpose()?
+    };
+    let mut quotes: u64 = 0;
+    // Where the first record after a newline starts, after an even and an odd number of quotes
+    let mut starts: [Option<u64>; 2] = [None, None];
+    // The byte before the quote just read and how many quotes came before it
+    let mut last_quote: Option<(Option<u8>, u64)> = None;
+    let mut position = offset;
+    loop {
+        let byte = if position < len {
+            bytes.next().transpose()?
+        } else {
+            None
+        };
+        if let Some((before, quotes_before)) = last_quote.take() {
+            let opens = byte.is_some_and(is_content);
+            let closes = !byte.is_some_and(|byte| byte == quote || is_content(byte))
+                && before.is_some_and(is_content);
+            if started_in_quotes.is_none() && (opens || closes) {
+                // An opening quote comes outside quotes and a closing one inside
+                started_in_quotes = Some((quotes_before % 2 == 1) == opens);
+            }
+        }
+        let Some(byte) = byte else {
+            break;
+        };
+        position += 1;
+        if byte == quote {
+            last_quote = Some((previous, quotes));
+            quotes += 1;
+        } else if byte == b'\n' {
+            starts[(quotes % 2) as usize].get_or_insert(position);
+        }
+        match started_in_quotes {
+            Some(in_quotes) => {
+                if let Some(start) = starts[usize::from(in_quotes)] {
+                    return Ok(Some(start));
+                }
+            }
+            None if position - offset >= RESYNC_WINDOW => break,
+            None => (),
+        }
+        previous = Some(byte);
+    }
+    let at_end = position >= len;
+    Ok(match started_in_quotes {
+        Some(in_quotes) => starts[usize::from(in_quotes)].or(Some(len)),
+        None if quotes == 0 => starts[0].or(if at_end { Some(len) } else { None }),
+        None => None,
+    })
+}
+
+fn narrow_type<'a>(current: &'a str, value: &str) -> &'a str {
+    if value.is_empty() {
+        return current;
+    }
+    match current {
+        "int" if value.parse::<i64>().is_ok() => "int",
+        "int" | "real" if value.parse::<f64>().is_ok() => "real",
+        _ => "text",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::PathBuf};
+
+    use crate::formats::{CsvOptions, CsvReader, FormatReader, Record};
+
+    fn write_temp(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("wings_{}_{}.csv", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn split_on_record_boundaries() {
+        let path = write_temp(
+            "split",
+            "id,note\n1,\"first\nline\"\n2,second\n3,\"third, \"\"quoted\"\"\nline\"\n4,fourth\n",
+        );
+        let ranges = CsvReader::default().split(&path, 4).unwrap().unwrap();
+        assert_eq!(ranges.first().unwrap().start, 8);
+        assert_eq!(
+            ranges.last().unwrap().end,
+            fs::metadata(&path).unwrap().len()
+        );
+
+        let mut notes: Vec<String> = Vec::new();
+        for range in ranges {
+            for row in CsvReader::default().read_range(&path, range).unwrap() {
+                notes.push(row.unwrap().get("note").unwrap().clone());
+            }
+        }
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            notes,
+            vec!["first\nline", "second", "third, \"quoted\"\nline", "fourth"]
+        );
+    }
+
+    #[test]
+    fn split_from_inside_quotes() {
+        let contents =
+            "id,note\n1,\"first\nline\"\n2,second\n3,\"third, \"\"quoted\"\"\nline\"\n4,fourth\n";
+        let path = write_temp("split_inside", contents);
+        let reader = CsvReader::default();
+        let rows: Vec<Record> = reader
+            .read(&path)
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        // Every split point, including ones inside quoted fields, lands on a record boundary
+        for chunk_size in 1..contents.len() as u64 {
+            let ranges = reader.split(&path, chunk_size).unwrap().unwrap();
+            let mut split_rows: Vec<Record> = Vec::new();
+            for range in ranges {
+                split_rows.extend(
+                    reader
+                        .read_range(&path, range)
+                        .unwrap()
+                        .map(|row| row.unwrap()),
+                );
+            }
+            assert_eq!(split_rows, rows, "chunk size {}", chunk_size);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unclear_quotes_are_not_split() {
+        // Quotes next to delimiters don't show whether they open or close a field
+        let path = write_temp("unclear", "a,b\n\"\",\"\"\n\"\",\"\"\n");
+        let ranges = CsvReader::default().split(&path, 5).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(ranges.is_none());
+    }
+
+    #[test]
+    fn count_records() {
+        let path = write_temp("count", "id,note\n1,\"a\nb\"\n\n2,c\n");
+        let count = CsvReader::default().count(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn read_with_options() {
+        let path = write_temp("options", "1;'a;b'\n2;c\n");
+        let reader = CsvReader::new(CsvOptions {
+            delimiter: ';',
+            quote: '\'',
+            has_headers: false,
+        });
+        let schema = reader.infer_schema(&path).unwrap();
+        let rows: Vec<_> = reader
+            .read(&path)
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        let ranges = reader.split(&path, 4).unwrap().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(schema.get("column_1").unwrap(), "int");
+        assert_eq!(schema.get("column_2").unwrap(), "text");
+        assert_eq!(rows[0].get("column_2").unwrap(), "a;b");
+        assert_eq!(ranges, vec![0..8, 8..12]);
+    }
+
+    #[test]
+    fn small_files_are_not_split() {
+        let path = write_temp("small", "id\n1\n");
+        let ranges = CsvReader::default().split(&path, 1024).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(ranges.is_none());
+    }
+}
@@ -0,0 +1,216 @@
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufRead, BufReader, Read, Seek, SeekFrom},
+    ops::Range,
+    path::Path,
+};
+
+use crate::error::{Result, WingsError};
+use crate::formats::{
+    has_extension, json_column_type, open_chunk, record_from_json, Chunk, FormatReader, Records,
+};
+
+/// Number of lines sampled when inferring column types
+const INFER_SAMPLE_SIZE: usize = 100;
+
+/// Newline delimited JSON objects
+pub struct JsonlReader;
+
+impl FormatReader for JsonlReader {
+    fn name(&self) -> &str {
+        "jsonl"
+    }
+
+    fn detect(&self, path: &Path) -> bool {
+        has_extension(path, "jsonl") || has_extension(path, "ndjson")
+    }
+
+    fn infer_schema(&self, path: &Path) -> Result<BTreeMap<String, String>> {
+        let mut schema: BTreeMap<String, String> = BTreeMap::new();
+        let reader = BufReader::new(File::open(path)?);
+        for line in reader.lines().take(INFER_SAMPLE_SIZE) {
+            let line = line?;
+            let Some(object) = parse_line(&line, path)? else {
+                continue;
+            };
+            for (column, value) in &object {
+                if value.is_null() {
+                    continue;
+                }
+                let column_type = json_column_type(value);
+                let entry = schema
+                    .entry(column.clone())
+                    .or_insert_with(|| column_type.to_string());
+                if entry != column_type {
+                    // Mixed ints and reals are real, anything else mixed is text
+                    let widened = matches!(
+                        (entry.as_str(), column_type),
+                        ("int", "real") | ("real", "int")
+                    );
+                    *entry = if widened { "real" } else { "text" }.to_string();
+                }
+            }
+        }
+        Ok(schema)
+    }
+
+    fn read<'a>(&self, path: &'a Path) -> Result<Records<'a>> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(records_from_lines(reader, path))
+    }
+
+    fn count(&self, path: &Path) -> Result<u64> {
+        // Lines are parsed so the count matches what loading keeps: objects
+        // without a value that isn't null are left out, invalid lines fail
+        let reader = BufReader::new(File::open(path)?);
+        let mut count = 0;
+        for line in reader.lines() {
+            if let Some(object) = parse_line(&line?, path)? {
+                if object.values().any(|value| !value.is_null()) {
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    fn split(&self, path: &Path, chunk_size: u64) -> Result<Option<Vec<Range<u64>>>> {
+        let len = fs::metadata(path)?.len();
+        if len <= chunk_size {
+            return Ok(None);
+        }
+        // JSON strings can't contain raw newlines so any newline ends a record
+        let mut ranges: Vec<Range<u64>> = Vec::new();
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut start: u64 = 0;
+        while start < len {
+            let target = start + chunk_size;
+            if target >= len {
+                ranges.push(start..len);
+                break;
+            }
+            reader.seek(SeekFrom::Start(target))?;
+            let mut skipped = Vec::new();
+            let end = target + reader.read_until(b'\n', &mut skipped)? as u64;
+            ranges.push(start..end);
+            start = end;
+        }
+        Ok(Some(ranges))
+    }
+
+    fn read_range<'a>(&self, path: &'a Path, range: Range<u64>) -> Result<Records<'a>> {
+        let mut file = File::open(path)?;
+        file.seek(SeekFrom::Start(range.start))?;
+        let reader = BufReader::new(file.take(range.end - range.start));
+        Ok(records_from_lines(reader, path))
+    }
+
+    fn read_chunk<'a>(&self, chunk: &'a Chunk, bytes: &'a Cell<u64>) -> Result<Records<'a>> {
+        let reader = BufReader::new(open_chunk(chunk, bytes)?);
+        Ok(records_from_lines(reader, &chunk.path))
+    }
+}
+
+fn records_from_lines<'a, R: BufRead + 'a>(reader: R, path: &'a Path) -> Records<'a> {
+    let records = reader.lines().filter_map(move |line| {
+        let line = match line {
+            Ok(line) => line,
+            Err(error) => return Some(Err(error.into())),
+        };
+        parse_line(&line, path)
+            .map(|object| object.map(|object| record_from_json(&object)))
+            .transpose()
+    });
+    Box::new(records)
+}
+
+/// Parse a line as a JSON object, skipping blank lines
+fn parse_line(
+    line: &str,
+    path: &Path,
+) -> Result<Option<serde_json::Map<String, serde_json::Value>>> {
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+    match serde_json::from_str::<serde_json::Value>(line) {
+        Ok(serde_json::Value::Object(object)) => Ok(Some(object)),
+        Ok(_) => Err(WingsError::Parse(format!(
+            "Expected a JSON object on each line of {}",
+            path.display()
+        ))),
+        Err(error) => Err(WingsError::Parse(format!(
+            "{} in {}",
+            error,
+            path.display()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use crate::formats::{FormatReader, JsonlReader};
+
+    #[test]
+    fn split_and_read_ranges() {
+        let path = env::temp_dir().join(format!("wings_split_{}.jsonl", std::process::id()));
+        fs::write(
+            &path,
+            "{\"id\": 1, \"name\": \"a\"}\n{\"id\": 2, \"name\": null}\n\n{\"id\": 3.5, \"name\": \"c\"}\n",
+        )
+        .unwrap();
+
+        let schema = JsonlReader.infer_schema(&path).unwrap();
+        assert_eq!(schema.get("id").unwrap(), "real");
+        assert_eq!(schema.get("name").unwrap(), "text");
+
+        assert_eq!(JsonlReader.count(&path).unwrap(), 3);
+
+        let ranges = JsonlReader.split(&path, 10).unwrap().unwrap();
+        let mut ids: Vec<String> = Vec::new();
+        for range in ranges {
+            for row in JsonlReader.read_range(&path, range).unwrap() {
+                let row = row.unwrap();
+                if row.get("id").unwrap() == "2" {
+                    assert!(!row.contains_key("name"));
+                }
+                ids.push(row.get("id").unwrap().clone());
+            }
+        }
+        fs::remove_file(&path).unwrap();
+        assert_eq!(ids, vec!["1", "2", "3.5"]);
+    }
+
+    #[test]
+    fn count_what_loading_keeps() {
+        let path = env::temp_dir().join(format!("wings_count_{}.jsonl", std::process::id()));
+        fs::write(&path, "{\"id\":1}\n{}\n{\"id\":null}\n\n{\"id\":2}\n").unwrap();
+        let count = JsonlReader.count(&path).unwrap();
+        fs::write(&path, "{\"id\":1}\nnot json\n").unwrap();
+        let invalid = JsonlReader.count(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(count, 2);
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn booleans_as_ints() {
+        let path = env::temp_dir().join(format!("wings_bool_{}.jsonl", std::process::id()));
+        fs::write(&path, "{\"flag\": true}\n{\"flag\": false}\n").unwrap();
+        let schema = JsonlReader.infer_schema(&path).unwrap();
+        let flags: Vec<String> = JsonlReader
+            .read(&path)
+            .unwrap()
+            .map(|row| row.unwrap()["flag"].clone())
+            .collect();
+        fs::remove_file(&path).unwrap();
+
+        // Stored the way the inferred type reads them, like Avro booleans
+        assert_eq!(schema["flag"], "int");
+        assert_eq!(flags, ["1", "0"]);
+    }
+}
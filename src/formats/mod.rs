@@ -0,0 +1,422 @@
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom, Take},
+    iter,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::error::{Result, WingsError};
+
+mod avro_reader;
+mod csv_reader;
+mod jsonl_reader;
+
+pub use avro_reader::AvroReader;
+pub use csv_reader::{CsvOptions, CsvReader};
+pub use jsonl_reader::JsonlReader;
+
+/// Files larger than this are split into chunks that are parsed in parallel
+pub const CHUNK_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Single record read from a file, keyed by column name
+pub type Record = BTreeMap<String, String>;
+
+/// Stream of records read from a single file
+pub type Records<'a> = Box<dyn Iterator<Item = Result<Record>> + 'a>;
+
+/// Reader for a file format that tables can be created with
+///
+/// Implement this and add it to a [`FormatRegistry`] to load a format wings
+/// doesn't know about.
+pub trait FormatReader: Send + Sync {
+    /// Name of the format as given in the table definition
+    fn name(&self) -> &str;
+
+    /// Whether the file at path should be read with this format
+    fn detect(&self, path: &Path) -> bool;
+
+    /// Infer column names and types from the file at path
+    fn infer_schema(&self, path: &Path) -> Result<BTreeMap<String, String>>;
+
+    /// Stream the records in the file at path
+    fn read<'a>(&self, path: &'a Path) -> Result<Records<'a>>;
+
+    /// Count the records in the file at path without loading them
+    /// Formats should override this when they can count faster than reading
+    fn count(&self, path: &Path) -> Result<u64> {
+        let mut count = 0;
+        for row in self.read(path)? {
+            if !row?.is_empty() {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Split the file at path into byte ranges of about chunk_size that
+    /// start and end on record boundaries
+    /// Formats that can't be split return None and are read whole
+    fn split(&self, _path: &Path, _chunk_size: u64) -> Result<Option<Vec<Range<u64>>>> {
+        Ok(None)
+    }
+
+    /// Stream the records in a byte range returned by split
+    fn read_range<'a>(&self, path: &'a Path, _range: Range<u64>) -> Result<Records<'a>> {
+        Err(WingsError::Parse(format!(
+            "Format {} can't read part of {}",
+            self.name(),
+            path.display()
+        )))
+    }
+
+    /// Stream the records in a chunk, adding the bytes taken from its file
+    /// to bytes as they're read
+    /// Formats should override this so a chunk that's only partly read
+    /// counts what was read rather than the whole chunk, which is all the
+    /// default can count once its records run out
+    fn read_chunk<'a>(&self, chunk: &'a Chunk, bytes: &'a Cell<u64>) -> Result<Records<'a>> {
+        let (records, size) = match &chunk.range {
+            Some(range) => (
+                self.read_range(&chunk.path, range.clone())?,
+                range.end - range.start,
+            ),
+            None => (self.read(&chunk.path)?, fs::metadata(&chunk.path)?.len()),
+        };
+        let mut counted = false;
+        let count = iter::from_fn(move || {
+            if !counted {
+                bytes.set(bytes.get() + size);
+                counted = true;
+            }
+            None
+        });
+        Ok(Box::new(records.chain(count)))
+    }
+}
+
+/// Open the part of a file a chunk covers, counting the bytes read from it
+pub fn open_chunk<'a>(
+    chunk: &Chunk,
+    bytes: &'a Cell<u64>,
+) -> Result<CountingReader<'a, Take<File>>> {
+    let mut file = File::open(&chunk.path)?;
+    let length = match &chunk.range {
+        Some(range) => {
+            file.seek(SeekFrom::Start(range.start))?;
+            range.end - range.start
+        }
+        None => u64::MAX,
+    };
+    Ok(CountingReader {
+        inner: file.take(length),
+        bytes,
+    })
+}
+
+/// Reader that adds the bytes read through it to a counter
+pub struct CountingReader<'a, R> {
+    inner: R,
+    bytes: &'a Cell<u64>,
+}
+
+impl<R: Read> Read for CountingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bytes.set(self.bytes.get() + read as u64);
+        Ok(read)
+    }
+}
+
+/// Part of a file to read, either all of it or a byte range from [`FormatReader::split`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub path: PathBuf,
+    pub range: Option<Range<u64>>,
+}
+
+/// Format readers by name
+#[derive(Clone)]
+pub struct FormatRegistry {
+    readers: HashMap<String, Arc<dyn FormatReader>>,
+}
+
+impl FormatRegistry {
+    /// Create a registry with no formats
+    pub fn empty() -> FormatRegistry {
+        FormatRegistry {
+            readers: HashMap::new(),
+        }
+    }
+
+    /// Add a format, replacing any format with the same name
+    pub fn register(&mut self, reader: impl FormatReader + 'static) {
+        self.readers
+            .insert(reader.name().to_string(), Arc::new(reader));
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn FormatReader>> {
+        self.readers.get(name).cloned()
+    }
+
+    /// Names of all registered formats
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.readers.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+impl Default for FormatRegistry {
+    /// Registry with the formats built into wings
+    fn default() -> Self {
+        let mut registry = FormatRegistry::empty();
+        registry.register(CsvReader::default());
+        registry.register(AvroReader);
+        registry.register(JsonlReader);
+        registry
+    }
+}
+
+/// Read every record in the file at path
+pub fn read_path(path: &Path, reader: &dyn FormatReader) -> Result<Vec<Record>> {
+    let mut rows: Vec<Record> = Vec::new();
+    if path.is_file() && reader.detect(path) {
+        for row in reader.read(path)? {
+            let row = row?;
+            if !row.is_empty() {
+                rows.push(row);
+            }
+        }
+    }
+    Ok(rows)
+}
+
+/// Get the chunks to read for files in the given format, in file order
+/// Files that aren't in the format are skipped
+pub fn chunk_files(
+    files: &[PathBuf],
+    reader: &dyn FormatReader,
+    chunk_size: u64,
+) -> Result<Vec<Chunk>> {
+    let mut chunks: Vec<Chunk> = Vec::new();
+    for path in files {
+        if !path.is_file() || !reader.detect(path) {
+            continue;
+        }
+        match reader.split(path, chunk_size)? {
+            Some(ranges) => chunks.extend(ranges.into_iter().map(|range| Chunk {
+                path: path.clone(),
+                range: Some(range),
+            })),
+            None => chunks.push(Chunk {
+                path: path.clone(),
+                range: None,
+            }),
+        }
+    }
+    Ok(chunks)
+}
+
+/// Read a chunk in batches of up to batch_size records, adding the bytes
+/// read from its file to bytes
+/// Stops early when send returns false
+pub fn read_chunk_batches(
+    chunk: &Chunk,
+    reader: &dyn FormatReader,
+    batch_size: usize,
+    bytes: &Cell<u64>,
+    mut send: impl FnMut(Vec<Record>) -> bool,
+) -> Result<()> {
+    let records = reader.read_chunk(chunk, bytes)?;
+    let mut batch: Vec<Record> = Vec::with_capacity(batch_size);
+    for row in records {
+        let row = row?;
+        if row.is_empty() {
+            continue;
+        }
+        batch.push(row);
+        if batch.len() == batch_size {
+            let full = std::mem::replace(&mut batch, Vec::with_capacity(batch_size));
+            if !send(full) {
+                return Ok(());
+            }
+        }
+    }
+    if !batch.is_empty() {
+        send(batch);
+    }
+    Ok(())
+}
+
+/// Convert a JSON object into a record
+/// Strings are stored without quotes, nulls are left out, and booleans are
+/// stored as 1 and 0 to match the int type they're inferred as
+pub fn record_from_json(object: &serde_json::Map<String, serde_json::Value>) -> Record {
+    let mut row: Record = BTreeMap::new();
+    for (column, val) in object {
+        let record = match val {
+            serde_json::Value::Null => continue,
+            serde_json::Value::String(s) => s.clone(),
+            serde_json::Value::Bool(b) => u8::from(*b).to_string(),
+            _ => val.to_string(),
+        };
+        row.insert(column.clone(), record);
+    }
+    row
+}
+
+/// Column type for a JSON value
+fn json_column_type(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Bool(_) => "int",
+        serde_json::Value::Number(n) if n.is_i64() || n.is_u64() => "int",
+        serde_json::Value::Number(_) => "real",
+        _ => "text",
+    }
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension().is_some_and(|e| e == extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        path::{Path, PathBuf},
+    };
+
+    use crate::formats::{
+        chunk_files, read_chunk_batches, read_path, Chunk, FormatRegistry, CHUNK_SIZE,
+    };
+
+    #[test]
+    fn default_registry_formats() {
+        let registry = FormatRegistry::default();
+        assert_eq!(registry.names(), vec!["avro", "csv", "jsonl"]);
+        assert!(registry.get("parquet").is_none());
+    }
+
+    #[test]
+    fn read_csv_path() {
+        let registry = FormatRegistry::default();
+        let csv = registry.get("csv").unwrap();
+        let rows = read_path(Path::new("./examples/data/datum.csv"), csv.as_ref()).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].get("id").unwrap(), "53");
+    }
+
+    #[test]
+    fn skip_other_formats() {
+        let registry = FormatRegistry::default();
+        let avro = registry.get("avro").unwrap();
+        let rows = read_path(Path::new("./examples/data/datum.csv"), avro.as_ref()).unwrap();
+        assert!(rows.is_empty());
+    }
+
+    #[test]
+    fn read_chunk_in_batches() {
+        let registry = FormatRegistry::default();
+        let avro = registry.get("avro").unwrap();
+        let chunk = Chunk {
+            path: PathBuf::from("./examples/avro_data/twitter.avro"),
+            range: None,
+        };
+        let mut batch_sizes = Vec::new();
+        let bytes = Cell::new(0);
+        read_chunk_batches(&chunk, avro.as_ref(), 1, &bytes, |batch| {
+            batch_sizes.push(batch.len());
+            true
+        })
+        .unwrap();
+        assert_eq!(batch_sizes, vec![1, 1]);
+        assert_eq!(bytes.get(), std::fs::metadata(&chunk.path).unwrap().len());
+    }
+
+    #[test]
+    fn count_bytes_read() {
+        let path = std::env::temp_dir().join(format!("wings_bytes_{}.jsonl", std::process::id()));
+        let lines: String = (0..10000)
+            .map(|id| format!("{{\"id\": {}}}\n", id))
+            .collect();
+        std::fs::write(&path, &lines).unwrap();
+        let registry = FormatRegistry::default();
+        let jsonl = registry.get("jsonl").unwrap();
+
+        // Stopping after the first batch leaves most of the file unread
+        let chunk = Chunk {
+            path: path.clone(),
+            range: None,
+        };
+        let stopped = Cell::new(0);
+        read_chunk_batches(&chunk, jsonl.as_ref(), 10, &stopped, |_| false).unwrap();
+
+        let end = lines.match_indices('\n').nth(99).unwrap().0 as u64 + 1;
+        let range = Chunk {
+            path: path.clone(),
+            range: Some(0..end),
+        };
+        let ranged = Cell::new(0);
+        read_chunk_batches(&range, jsonl.as_ref(), 10, &ranged, |_| true).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(stopped.get() > 0);
+        assert!(stopped.get() < lines.len() as u64 / 2);
+        assert_eq!(ranged.get(), end);
+    }
+
+    #[test]
+    fn chunk_files_in_format() {
+        let registry = FormatRegistry::default();
+        let csv = registry.get("csv").unwrap();
+        let files = vec![
+            PathBuf::from("./examples/data"),
+            PathBuf::from("./examples/data/datum.csv"),
+            PathBuf::from("./examples/avro_data/twitter.avro"),
+        ];
+        let chunks = chunk_files(&files, csv.as_ref(), CHUNK_SIZE).unwrap();
+        assert_eq!(
+            chunks,
+            vec![Chunk {
+                path: PathBuf::from("./examples/data/datum.csv"),
+                range: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn infer_csv_schema() {
+        let registry = FormatRegistry::default();
+        let csv = registry.get("csv").unwrap();
+        let schema = csv
+            .infer_schema(Path::new("./examples/data/datum.csv"))
+            .unwrap();
+        assert_eq!(schema.get("id").unwrap(), "int");
+        assert_eq!(schema.get("col1").unwrap(), "text");
+    }
+
+    #[test]
+    fn count_avro_blocks() {
+        let registry = FormatRegistry::default();
+        let avro = registry.get("avro").unwrap();
+        let count = avro
+            .count(Path::new("./examples/avro_data/twitter.avro"))
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn infer_avro_schema() {
+        let registry = FormatRegistry::default();
+        let avro = registry.get("avro").unwrap();
+        let schema = avro
+            .infer_schema(Path::new("./examples/avro_data/twitter.avro"))
+            .unwrap();
+        assert_eq!(schema.get("username").unwrap(), "text");
+        assert_eq!(schema.get("timestamp").unwrap(), "int");
+    }
+}
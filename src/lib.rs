@@ -0,0 +1,51 @@
+//! Query tool for files with on the fly data loading.
+//!
+//! The [`Wings`] session lets other programs register tables and run SQL
+//! against them without going through the CLI or the `~/.wings` catalog.
+//!
+//! ```no_run
+//! use std::collections::BTreeMap;
+//! use std::path::PathBuf;
+//!
+//! use wings::metadata::{Format, Metadata, TableMetadata};
+//! use wings::Wings;
+//!
+//! let mut schema = BTreeMap::new();
+//! schema.insert("id".to_string(), "int".to_string());
+//!
+//! let mut wings = Wings::new().unwrap();
+//! wings.register_table(TableMetadata {
+//!     metadata: Metadata {
+//!         name: "events".to_string(),
+//!         format: Format::Csv,
+//!         data_path: PathBuf::from("./data/events"),
+//!         ..Default::default()
+//!     },
+//!     schema,
+//!     constraints: BTreeMap::new(),
+//! }).unwrap();
+//!
+//! for row in wings.query("select id from events").unwrap() {
+//!     println!("{:?}", row.get("id"));
+//! }
+//! ```
+
+pub mod data_loader;
+pub mod display_row;
+pub mod error;
+pub mod explain;
+mod for_loop_loader;
+pub mod formats;
+pub mod metadata;
+pub mod pg_server;
+pub mod program;
+pub mod progress;
+pub mod query_parser;
+mod rayon_loader;
+pub mod server;
+pub mod session;
+pub mod stats;
+pub mod utils;
+
+pub use error::WingsError;
+pub use session::{QueryResult, Row, Wings};
@@ -1,22 +1,25 @@
-use std::{collections::HashMap, path::PathBuf, time::SystemTime};
+use std::{fs, io, path::PathBuf, process, time::Instant};
 
 use clap::{Parser, Subcommand};
-use sqlite::Error;
 
-use crate::metadata::{get_metadata_for_display, render_tables};
+use wings::formats::CsvOptions;
+use wings::metadata::{
+    self, get_metadata_for_display, render_tables, render_views, Alteration, CatalogBundle,
+    ErrorPolicy, Format, FormatOptions, Index,
+};
+use wings::pg_server::PgServer;
+use wings::server::Server;
+use wings::{display_row, program, Wings, WingsError};
 
-mod data_loader;
-mod metadata;
-mod program;
-mod query_parser;
-mod display_row;
-mod rayon_loader;
-mod utils;
-mod for_loop_loader;
+/// Name of the table read from stdin
+const STDIN_TABLE: &str = "stdin";
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
+    /// Catalog directory to use instead of $WINGS_HOME, a project's .wings or ~/.wings
+    #[arg(long, global = true, value_name = "DIR")]
+    catalog: Option<PathBuf>,
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -24,7 +27,11 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Initialize program
-    Init,
+    Init {
+        /// Create a project catalog in .wings under the current directory
+        #[arg(long)]
+        local: bool,
+    },
     /// Create table to query
     Create {
         /// Name of table
@@ -37,8 +44,42 @@ enum Commands {
         #[arg(short, long)]
         file_path: String,
         /// Format of files to load
+        #[arg(long, value_enum, ignore_case = true)]
+        format: Format,
+        /// Field delimiter for csv files
+        #[arg(long, value_name = "CHAR")]
+        delimiter: Option<char>,
+        /// Quote character for csv files
+        #[arg(long, value_name = "CHAR")]
+        quote: Option<char>,
+        /// Csv files have no header row, so columns are named column_1, column_2, ...
+        #[arg(long)]
+        no_headers: bool,
+        /// Number rows in file order in a `_row` column
         #[arg(long)]
-        format: String, // Make into Enum
+        row_numbers: bool,
+        /// What to do with rows that break a column constraint
+        #[arg(long, value_enum, default_value_t = ErrorPolicy::Fail)]
+        on_error: ErrorPolicy,
+    },
+    /// Store a named query that other queries can read like a table
+    CreateView {
+        /// Name of view
+        name: String,
+        /// Select query the view runs
+        query: String,
+    },
+    /// Drop given view
+    DropView {
+        /// View to drop
+        name: String,
+    },
+    /// Change a table's definition
+    Alter {
+        /// Table to alter
+        table: String,
+        #[command(subcommand)]
+        command: AlterCommands,
     },
     /// Drop given table
     Drop {
@@ -46,149 +87,289 @@ enum Commands {
         // #[arg(short, long)]
         table: String,
     },
+    /// Print table and view definitions as yaml to share with another catalog
+    ExportCatalog {
+        /// Tables and views to export, all of them when none are given
+        names: Vec<String>,
+    },
+    /// Add table and view definitions written by export-catalog
+    ImportCatalog {
+        /// Yaml file written by export-catalog
+        file: PathBuf,
+        /// Replace tables and views that already exist
+        #[arg(long)]
+        overwrite: bool,
+    },
     /// Query data using wings
     Query {
         /// Query to execute
         // #[arg(short, long)]
         query: String,
+        /// Show how the query would be loaded and run without loading data
+        #[arg(long)]
+        explain: bool,
+        /// Show time spent on each stage and what was read for each table
+        #[arg(long)]
+        stats: bool,
+        /// Read rows piped to stdin as a table named `stdin` in this format
+        #[arg(long, value_name = "FORMAT", value_enum, ignore_case = true)]
+        stdin_format: Option<Format>,
+        /// Schema of the stdin table, a schema yaml file or columns like
+        /// `id: int, name: text`, inferred from the data when not given
+        #[arg(long, value_name = "SCHEMA", requires = "stdin_format")]
+        stdin_schema: Option<String>,
+        /// Index the columns the query joins and filters on after loading
+        #[arg(long)]
+        auto_index: bool,
+    },
+    /// Serve queries over HTTP on localhost
+    Serve {
+        /// Port to listen on
+        #[arg(short, long, default_value_t = 8080)]
+        port: u16,
+    },
+    /// Serve queries over the Postgres wire protocol on localhost
+    Pgserve {
+        /// Port to listen on
+        #[arg(short, long, default_value_t = 5432)]
+        port: u16,
     },
-    /// Show information about objects
+    /// Show information about objects, all tables by default
     Show {
         #[command(subcommand)]
         command: Option<ShowCommands>,
-    }
+    },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum AlterCommands {
+    /// Add a column
+    AddColumn { name: String, column_type: String },
+    /// Drop a column
+    DropColumn { name: String },
+    /// Rename a column
+    RenameColumn { name: String, new_name: String },
+    /// Change a column's type
+    SetType { name: String, column_type: String },
+    /// Change the path to search for data
+    SetPath { file_path: PathBuf },
+    /// Change the format of files to load
+    SetFormat {
+        #[arg(value_enum, ignore_case = true)]
+        format: Format,
+    },
+    /// Change what happens to rows that break a column constraint
+    SetOnError {
+        #[arg(value_enum)]
+        on_error: ErrorPolicy,
+    },
+    /// Add an index, built after the table is loaded
+    AddIndex {
+        #[arg(required = true)]
+        columns: Vec<String>,
+        /// Fail loading when two rows have the same values in the columns
+        #[arg(long)]
+        unique: bool,
+    },
+    /// Drop the index on the given columns
+    DropIndex {
+        #[arg(required = true)]
+        columns: Vec<String>,
+    },
+    /// Rename the table
+    Rename { new_name: String },
 }
 
 #[derive(Debug, Subcommand)]
 enum ShowCommands {
     /// Show all tables created by wings
     Tables,
+    /// Show all views and their queries
+    Views,
     /// Show information for a given table
-    Table { 
-        name: String 
-    },
+    Table { name: String },
 }
 
 fn main() {
     let cli = Cli::parse();
     simple_logger::SimpleLogger::new().env().init().unwrap();
+    if let Some(catalog) = &cli.catalog {
+        program::set_catalog_dir(catalog.clone());
+    }
 
-    let not_initialized_message = "Wings not initialized, run `wings init` first.";
+    if let Err(error) = run(&cli) {
+        eprintln!("Error: {}", error);
+        process::exit(1);
+    }
+}
 
+fn run(cli: &Cli) -> Result<(), WingsError> {
     match &cli.command {
-        Some(Commands::Init) => {
-            program::init();
-            println!("Init complete.")
+        Some(Commands::Init { local }) => {
+            if *local {
+                program::init_local()?;
+            } else {
+                program::init()?;
+            }
+            println!(
+                "Init complete, tables are stored in {}",
+                program::tables_dir()?.display()
+            )
         }
         Some(Commands::Create {
             table,
             config,
             file_path,
             format,
+            delimiter,
+            quote,
+            no_headers,
+            row_numbers,
+            on_error,
         }) => {
-            if program::does_program_directory_exist() {
-                let _ = metadata::create_table(table, config, file_path, format);
-            } else {
-                println!("{}", not_initialized_message)
+            program::ensure_initialized()?;
+            let mut options = FormatOptions::default();
+            if delimiter.is_some() || quote.is_some() || *no_headers {
+                let defaults = CsvOptions::default();
+                options.csv = Some(CsvOptions {
+                    delimiter: delimiter.unwrap_or(defaults.delimiter),
+                    quote: quote.unwrap_or(defaults.quote),
+                    has_headers: !no_headers,
+                });
             }
+            metadata::create_table(
+                table,
+                config,
+                file_path,
+                format.clone(),
+                options,
+                *row_numbers,
+                *on_error,
+            )?;
+            println!("Table {} created", table);
+        }
+        Some(Commands::Alter { table, command }) => {
+            program::ensure_initialized()?;
+            let alteration = match command.clone() {
+                AlterCommands::AddColumn { name, column_type } => {
+                    Alteration::AddColumn { name, column_type }
+                }
+                AlterCommands::DropColumn { name } => Alteration::DropColumn { name },
+                AlterCommands::RenameColumn { name, new_name } => {
+                    Alteration::RenameColumn { name, new_name }
+                }
+                AlterCommands::SetType { name, column_type } => {
+                    Alteration::SetColumnType { name, column_type }
+                }
+                AlterCommands::SetPath { file_path } => Alteration::SetDataPath(file_path),
+                AlterCommands::SetFormat { format } => Alteration::SetFormat(format),
+                AlterCommands::SetOnError { on_error } => Alteration::SetErrorPolicy(on_error),
+                AlterCommands::AddIndex { columns, unique } => {
+                    Alteration::AddIndex(Index { columns, unique })
+                }
+                AlterCommands::DropIndex { columns } => Alteration::DropIndex(columns),
+                AlterCommands::Rename { new_name } => Alteration::Rename(new_name),
+            };
+            metadata::alter_table(table, alteration)?;
+            println!("Table {} altered", table);
+        }
+        Some(Commands::CreateView { name, query }) => {
+            program::ensure_initialized()?;
+            metadata::create_view(name, query)?;
+            println!("View {} created", name);
+        }
+        Some(Commands::DropView { name }) => {
+            program::ensure_initialized()?;
+            metadata::drop_view(name)?;
+            println!("View {} dropped", name)
         }
         Some(Commands::Drop { table }) => {
-            if program::does_program_directory_exist() {
-                metadata::drop_table(table);
-            } else {
-                println!("{}", not_initialized_message)
+            program::ensure_initialized()?;
+            metadata::drop_table(table)?;
+            println!("Table {} dropped", table)
+        }
+        Some(Commands::ExportCatalog { names }) => {
+            program::ensure_initialized()?;
+            let bundle = metadata::export_catalog(names)?;
+            print!("{}", serde_yaml::to_string(&bundle)?);
+        }
+        Some(Commands::ImportCatalog { file, overwrite }) => {
+            program::ensure_initialized()?;
+            let bundle: CatalogBundle = serde_yaml::from_str(&fs::read_to_string(file)?)?;
+            metadata::import_catalog(&bundle, *overwrite)?;
+            for table in &bundle.tables {
+                println!("Table {} imported", table.metadata.name);
+            }
+            for view in &bundle.views {
+                println!("View {} imported", view.name);
             }
         }
-        Some(Commands::Query { query }) => {
-            if program::does_program_directory_exist() {
-                let now = SystemTime::now();
-                let _ = run_query(query);
-                match now.elapsed() {
-                    Ok(elapsed) => {
-                        println!("Query ran in {}ms", elapsed.as_millis());
-                    }
-                    Err(e) => {
-                        // an error occurred!
-                        println!("Error: {e:?}");
-                    }
-                }
+        Some(Commands::Query {
+            query,
+            explain,
+            stats,
+            stdin_format,
+            stdin_schema,
+            auto_index,
+        }) => {
+            let mut wings = Wings::new()?;
+            if let Some(format) = stdin_format {
+                let columns = match stdin_schema {
+                    Some(schema) => Some(metadata::parse_schema(schema)?),
+                    None => None,
+                };
+                wings.register_input(STDIN_TABLE, io::stdin().lock(), format.clone(), columns)?;
+            }
+            wings.register_from_catalog(query)?;
+            wings.set_auto_index(*auto_index);
+            if *explain {
+                println!("{}", wings.explain(query)?);
             } else {
-                println!("{}", not_initialized_message)
+                let now = Instant::now();
+                run_query(&mut wings, query, *stats)?;
+                println!("Query ran in {}ms", now.elapsed().as_millis());
             }
         }
+        Some(Commands::Serve { port }) => {
+            program::ensure_initialized()?;
+            Server::new(Wings::new()?).serve(*port)?;
+        }
+        Some(Commands::Pgserve { port }) => {
+            program::ensure_initialized()?;
+            PgServer::new(Wings::new()?).serve(*port)?;
+        }
         Some(Commands::Show { command }) => {
+            program::ensure_initialized()?;
             match command {
-                Some(table) => {
-                    match table {
-                        ShowCommands::Tables => {
-                            render_tables();
-                        },
-                        ShowCommands::Table { name } => {
-                            match get_metadata_for_display(name) {
-                                Ok(metadata) => println!("{}", metadata),
-                                Err(error) => println!("{}", error),
-                            }
-                        },
-                    }
-                },
-                None => todo!(),
+                Some(ShowCommands::Table { name }) => {
+                    println!("{}", get_metadata_for_display(name)?);
+                }
+                Some(ShowCommands::Views) => render_views()?,
+                Some(ShowCommands::Tables) | None => render_tables()?,
             }
         }
         None => {
             println!("Use --help for command details.")
         }
     }
+    Ok(())
 }
 
-fn run_query(query: &String) -> Result<(), Error> {
+fn run_query(wings: &mut Wings, query: &str, stats: bool) -> Result<(), WingsError> {
     println!("Running query...");
-    let tables = query_parser::get_tables_from_query(query);
-
-    let missing_tables = tables.iter().any(|table| {
-        let table_path = metadata::get_path_for_table(&table);
-        if !table_path.exists() {
-            println!(
-                "Table {} doesn't exist. Create it first with `wings create`",
-                table
-            );
-            return true;
-        } else {
-            return false;
-        }
-    });
-
-    if missing_tables {
-        return Ok(());
-    }
+    wings.set_progress(true);
 
-    let table_paths: Vec<PathBuf> = tables
+    // Query
+    let result = wings.query(query)?;
+    let rows = result
+        .rows()
         .iter()
-        .map(|table| {
-            let table_path = metadata::get_path_for_table(&table);
-            return table_path;
-        })
+        .map(|row| row.to_string_map())
         .collect();
-
-    let connection = sqlite::open(":memory:")?;
-    // let connection = sqlite::open("./database").unwrap();
-    data_loader::load(&connection, table_paths);
-
-    // Query
-    let mut rows: Vec<HashMap<String, String>> = Vec::new();
-    connection
-        .iterate(query, |pairs| {
-            // Render logic
-            let mut row: HashMap<String, String> = HashMap::new();
-            for &(name, value) in pairs.iter() {
-                match value {
-                    Some(wvalue) => row.insert(name.to_string(), wvalue.to_string()),
-                    None => row.insert(name.to_string(), "NULL".to_string()),
-                };
-            }
-            rows.push(row);
-            true
-        })?;
     let display_rows = display_row::display_rows_from_maps(rows);
     display_row::render(display_rows);
+    if stats {
+        println!("{}", result.stats());
+    }
     Ok(())
 }
@@ -1,199 +1,1916 @@
-use std::{collections::BTreeMap, fs, io::{Error, ErrorKind}, path::PathBuf};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt, fs,
+    path::{self, Path, PathBuf},
+};
 
-use serde::{Deserialize, Serialize};
-use serde_yaml::Error as SerdeError;
+use clap::{builder::PossibleValue, ValueEnum};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use walkdir::WalkDir;
 
-pub fn get_path_for_table(table: &String) -> PathBuf {
-    let homedir = dirs::home_dir().unwrap_or_else(|| {
-        panic!("Cannot find home directory, create home directory to continue.")
-    });
-    let mut table_path = homedir.clone();
-    table_path.push(".wings");
-    table_path.push("tables");
-    table_path.push(table.to_owned() + ".yaml");
-    return table_path;
+use crate::data_loader;
+use crate::error::{Result, WingsError};
+use crate::formats::{CsvOptions, FormatRegistry};
+use crate::program;
+use crate::query_parser;
+use crate::utils::{
+    canonicalize_path, normalize_path, quote_identifier, relative_path, schema_to_db,
+    split_table_name,
+};
+
+/// Get the path of a table's definition
+/// Tables in a namespace, like `billing.invoices`, are in a directory named
+/// after it
+pub fn get_path_for_table(table: &str) -> Result<PathBuf> {
+    let mut table_path = program::tables_dir()?;
+    let (namespace, name) = split_table_name(table);
+    if let Some(namespace) = namespace {
+        table_path.push(namespace);
+    }
+    table_path.push(name.to_owned() + ".yaml");
+    Ok(table_path)
 }
 
-pub fn get_tables() -> Vec<String> {
-    let homedir = dirs::home_dir().unwrap_or_else(|| {
-        panic!("Cannot find home directory, create home directory to continue.")
-    });
-    let mut table_path = homedir.clone();
-    table_path.push(".wings");
-    table_path.push("tables");
-
-    let tables: Vec<String> = WalkDir::new(table_path)
-        .into_iter()
-        .map(|f| match f {
-            Ok(entry) => {
-              if entry.path().is_file() {
-                entry
-                  .file_name()
-                  .to_str()
-                  .unwrap()
-                  .to_string()
-                  .replace(".yaml", "")
-              } else {
-                String::from(".")
-              }
-              },
-            Err(_) => todo!(),
-        })
-        .collect();
-    return tables.into_iter().filter(|t| *t != String::from(".")).collect();
+/// Get the name of every table in the catalog, sorted, with tables in a
+/// namespace named like `billing.invoices`
+pub fn get_tables() -> Result<Vec<String>> {
+    let tables_dir = program::tables_dir()?;
+
+    let mut tables: Vec<String> = Vec::new();
+    for entry in WalkDir::new(&tables_dir).max_depth(2) {
+        let entry = entry?;
+        let path = entry.path();
+        if !path.is_file() || path.extension().is_none_or(|extension| extension != "yaml") {
+            continue;
+        }
+        let name = path.with_extension("");
+        let name = name.strip_prefix(&tables_dir).unwrap_or(&name);
+        let parts: Vec<String> = name
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().to_string())
+            .collect();
+        tables.push(parts.join("."));
+    }
+    tables.sort();
+    Ok(tables)
 }
 
 pub fn create_table(
-    table: &String,
-    config: &PathBuf,
-    file_path: &String,
-    format: &String,
-) -> Result<(), SerdeError> {
+    table: &str,
+    config: &Path,
+    file_path: &str,
+    format: Format,
+    options: FormatOptions,
+    row_numbers: bool,
+    on_error: ErrorPolicy,
+) -> Result<()> {
     // Add table and config to dir
     // Strcuture
     // ~/.wings/tables/$tableName.toml
-    let table_path = get_path_for_table(table);
+    let table_path = get_path_for_table(table)?;
 
     if table_path.exists() {
-        println!("Table {} already exists. Drop and create to update.", table);
+        return Err(WingsError::Metadata(format!(
+            "Table {} already exists. Drop and create or alter to update.",
+            table
+        )));
+    }
+    check_view_does_not_exist(table)?;
+    check_format_is_known(&format)?;
+    options.validate(&format)?;
+    // Create
+    let mut table_metadata = TableMetadata {
+        metadata: Metadata {
+            name: table.to_string(),
+            format,
+            options,
+            data_path: stored_data_path(Path::new(file_path))?,
+            row_numbers,
+            on_error,
+            indexes: Vec::new(),
+        },
+        schema: BTreeMap::new(),
+        constraints: BTreeMap::new(),
+    };
+    table_metadata.set_columns(read_schema(config)?);
+    table_metadata.validate()?;
+    data_loader::check_schema_against_files(&table_metadata, &FormatRegistry::default())?;
+    write_table_metadata(&table_path, &table_metadata)
+}
+
+/// Change to a table definition made with `wings alter`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Alteration {
+    AddColumn { name: String, column_type: String },
+    DropColumn { name: String },
+    RenameColumn { name: String, new_name: String },
+    SetColumnType { name: String, column_type: String },
+    SetDataPath(PathBuf),
+    SetFormat(Format),
+    SetErrorPolicy(ErrorPolicy),
+    AddIndex(Index),
+    DropIndex(Vec<String>),
+    Rename(String),
+}
+
+/// Apply an alteration to a table's definition in the catalog
+pub fn alter_table(table: &str, alteration: Alteration) -> Result<()> {
+    let table_path = get_path_for_table(table)?;
+    if !table_path.exists() {
+        return Err(WingsError::NotFound(format!(
+            "Table {} does not exist to alter.",
+            table
+        )));
+    }
+    // The definition is edited as written, so relative paths stay relative
+    let mut table_metadata = read_table_definition(&table_path)?;
+
+    let new_path = match &alteration {
+        Alteration::Rename(new_name) => {
+            let new_path = get_path_for_table(new_name)?;
+            if new_path.exists() {
+                return Err(WingsError::Metadata(format!(
+                    "Table {} already exists.",
+                    new_name
+                )));
+            }
+            check_view_does_not_exist(new_name)?;
+            Some(new_path)
+        }
+        _ => None,
+    };
+    table_metadata.alter(alteration)?;
+    table_metadata.validate()?;
+
+    match new_path {
+        Some(new_path) => {
+            write_table_metadata(&new_path, &table_metadata)?;
+            fs::remove_file(&table_path)?;
+            remove_empty_namespace(table, &table_path)
+        }
+        None => write_table_metadata(&table_path, &table_metadata),
+    }
+}
+
+/// Data path to store for one given on the command line
+/// Relative paths are taken from the current directory, not wherever the
+/// table is queried from later, and stay relative to the tables directory so
+/// a catalog checked into a project works wherever the project is
+fn stored_data_path(data_path: &Path) -> Result<PathBuf> {
+    if data_path.is_absolute() {
+        return Ok(data_path.to_path_buf());
+    }
+    Ok(relative_to_tables_dir(
+        &path::absolute(data_path)?,
+        &program::tables_dir()?,
+    ))
+}
+
+/// Path from the tables directory to an absolute data path
+/// The `..`s in it are followed through symlinks when it's read, so it's
+/// worked out between the real directories, and the path is kept absolute
+/// when either of them can't be resolved
+fn relative_to_tables_dir(data_path: &Path, tables_dir: &Path) -> PathBuf {
+    match (canonicalize_path(data_path), tables_dir.canonicalize()) {
+        (Ok(data_path), Ok(tables_dir)) => relative_path(&data_path, &tables_dir),
+        _ => data_path.to_path_buf(),
+    }
+}
+
+fn read_table_definition(table_path: &Path) -> Result<TableMetadata> {
+    let contents = fs::read_to_string(table_path)?;
+    Ok(serde_yaml::from_str(&contents)?)
+}
+
+fn write_table_metadata(table_path: &Path, table_metadata: &TableMetadata) -> Result<()> {
+    if let Some(dir) = table_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let yaml = serde_yaml::to_string(table_metadata)?;
+    fs::write(table_path, yaml)?;
+    Ok(())
+}
+
+/// Read a schema yaml file of column names to types, or to a type with
+/// constraints
+pub fn read_schema(config: &Path) -> Result<BTreeMap<String, Column>> {
+    let contents = fs::read_to_string(config)?;
+    Ok(serde_yaml::from_str(&contents)?)
+}
+
+/// Read a schema given on the command line, either the path to a schema
+/// yaml file or columns written inline like `id: int, name: text`
+pub fn parse_schema(schema: &str) -> Result<BTreeMap<String, Column>> {
+    if Path::new(schema).is_file() {
+        return read_schema(Path::new(schema));
+    }
+    // Inline columns are the body of a yaml flow mapping
+    serde_yaml::from_str(&format!("{{{}}}", schema)).map_err(|error| {
+        WingsError::Schema(format!(
+            "{:?} isn't a schema file or columns like `id: int, name: text`: {}",
+            schema, error
+        ))
+    })
+}
+
+/// Named query stored in the catalog, read by other queries like a table
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ViewMetadata {
+    pub name: String,
+    pub query: String,
+}
+
+pub fn get_path_for_view(view: &str) -> Result<PathBuf> {
+    let mut view_path = program::views_dir()?;
+    view_path.push(view.to_owned() + ".yaml");
+    Ok(view_path)
+}
+
+fn check_view_does_not_exist(name: &str) -> Result<()> {
+    if get_path_for_view(name)?.exists() {
+        Err(WingsError::Metadata(format!(
+            "{} is already the name of a view.",
+            name
+        )))
     } else {
-        // Create
-        let mut map: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
-        let mut meta_map: BTreeMap<String, String> = BTreeMap::new();
-        // name
-        meta_map.insert("name".to_string(), table.to_string());
-        // format
-        meta_map.insert("format".to_string(), format.to_string());
-        // data_path
-        meta_map.insert("data_path".to_string(), file_path.to_string());
-        map.insert("metadata".to_string(), meta_map);
-        // schema
-        let contents = fs::read_to_string(config).unwrap();
-        let schema_map: BTreeMap<String, String> = serde_yaml::from_str(&contents)?;
-        map.insert("schema".to_string(), schema_map);
-        let yaml = serde_yaml::to_string(&map)?;
-        let _ = fs::write(table_path, yaml);
-        println!("Table {} created", table);
+        Ok(())
+    }
+}
+
+/// Store a view in the catalog
+/// Every table and view it reads has to exist already
+pub fn create_view(view: &str, query: &str) -> Result<()> {
+    check_view_name(view)?;
+    let view_path = get_path_for_view(view)?;
+    if view_path.exists() {
+        return Err(WingsError::Metadata(format!(
+            "View {} already exists. Drop and create to update.",
+            view
+        )));
+    }
+    if get_path_for_table(view)?.exists() {
+        return Err(WingsError::Metadata(format!(
+            "{} is already the name of a table.",
+            view
+        )));
+    }
+    let query = check_view_query(view, query, &|name| {
+        Ok(get_path_for_table(name)?.exists() || get_path_for_view(name)?.exists())
+    })?;
+    write_view_metadata(&ViewMetadata {
+        name: view.to_string(),
+        query,
+    })
+}
+
+fn check_view_name(view: &str) -> Result<()> {
+    check_table_name(view)?;
+    // Views are made as temporary views so they can read any namespace, and
+    // those can't be in a namespace themselves
+    if split_table_name(view).0.is_some() {
+        return Err(WingsError::Metadata(format!(
+            "View {} can't be in a namespace, only tables can",
+            view
+        )));
     }
     Ok(())
 }
 
-pub fn drop_table(table: &String) {
-    let table_path = get_path_for_table(table);
+/// Check a view's query reads only tables and views that exist, and return it
+/// without a trailing semicolon
+fn check_view_query(
+    view: &str,
+    query: &str,
+    exists: &dyn Fn(&str) -> Result<bool>,
+) -> Result<String> {
+    let query = query.trim().trim_end_matches(';').trim_end();
+    let tokens = query_parser::tokenize(query);
+    if !tokens
+        .first()
+        .is_some_and(|token| token.is_keyword("select") || token.is_keyword("with"))
+    {
+        return Err(WingsError::Parse(format!(
+            "View {} must be a single select query",
+            view
+        )));
+    }
+    if tokens.contains(&query_parser::Token::Symbol(';')) {
+        return Err(WingsError::Parse(format!(
+            "View {} must be a single select query",
+            view
+        )));
+    }
+    // Tables made for files only last for one query
+    if !query_parser::get_file_references(query).is_empty() {
+        return Err(WingsError::Parse(format!(
+            "View {} can't read files directly, create a table for them first",
+            view
+        )));
+    }
+    for name in query_parser::get_tables_from_tokens(&tokens) {
+        if name == view {
+            return Err(WingsError::Parse(format!(
+                "View {} can't read itself",
+                view
+            )));
+        }
+        if !exists(&name)? {
+            return Err(WingsError::Metadata(format!(
+                "View {} reads {}, which isn't a table or view",
+                view, name
+            )));
+        }
+    }
+    // SQLite checks the syntax, the tables it reads needn't exist for that
+    let connection = sqlite::open(":memory:")?;
+    connection
+        .execute(format!(
+            "create temp view {} as {}",
+            quote_identifier(view),
+            query
+        ))
+        .map_err(|error| {
+            WingsError::Parse(format!(
+                "Invalid query for view {}: {}",
+                view,
+                error.message.unwrap_or_default()
+            ))
+        })?;
+    Ok(query.to_string())
+}
+
+fn write_view_metadata(view_metadata: &ViewMetadata) -> Result<()> {
+    fs::create_dir_all(program::views_dir()?)?;
+    fs::write(
+        get_path_for_view(&view_metadata.name)?,
+        serde_yaml::to_string(view_metadata)?,
+    )?;
+    Ok(())
+}
+
+pub fn drop_view(view: &str) -> Result<()> {
+    let view_path = get_path_for_view(view)?;
+    if view_path.exists() {
+        fs::remove_file(view_path)?;
+        Ok(())
+    } else {
+        Err(WingsError::NotFound(format!(
+            "View {} does not exist to drop.",
+            view
+        )))
+    }
+}
+
+pub fn get_view_metadata_by_name(view: &str) -> Result<ViewMetadata> {
+    let view_path = get_path_for_view(view)?;
+    if !view_path.exists() {
+        return Err(WingsError::NotFound(format!(
+            "View {} doesn't exist. Create it first with `wings create-view`",
+            view
+        )));
+    }
+    Ok(serde_yaml::from_str(&fs::read_to_string(view_path)?)?)
+}
+
+/// Names of the views in the catalog, sorted
+pub fn get_views() -> Result<Vec<String>> {
+    let views_dir = program::views_dir()?;
+    if !views_dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut views: Vec<String> = Vec::new();
+    for entry in fs::read_dir(views_dir)? {
+        let path = entry?.path();
+        if path
+            .extension()
+            .is_some_and(|extension| extension == "yaml")
+        {
+            if let Some(name) = path.file_stem() {
+                views.push(name.to_string_lossy().to_string());
+            }
+        }
+    }
+    views.sort();
+    Ok(views)
+}
+
+pub fn drop_table(table: &str) -> Result<()> {
+    let table_path = get_path_for_table(table)?;
     if table_path.exists() {
-        let _ = fs::remove_file(table_path);
-        println!("Table {} dropped", table)
+        fs::remove_file(&table_path)?;
+        remove_empty_namespace(table, &table_path)
     } else {
-        println!("Table does not exist to drop.")
+        Err(WingsError::NotFound(format!(
+            "Table {} does not exist to drop.",
+            table
+        )))
     }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
-enum Format {
+/// Namespaces only exist while they have tables, so remove the directory of
+/// a namespaced table once its last definition is gone
+fn remove_empty_namespace(table: &str, table_path: &Path) -> Result<()> {
+    if let (Some(_), Some(dir)) = (split_table_name(table).0, table_path.parent()) {
+        if fs::read_dir(dir)?.next().is_none() {
+            fs::remove_dir(dir)?;
+        }
+    }
+    Ok(())
+}
+
+/// Table and view definitions written by `wings export-catalog`, to be
+/// added to another catalog with `wings import-catalog`
+#[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct CatalogBundle {
+    /// Tables directory of the catalog the bundle was exported from, which
+    /// relative data paths are relative to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tables_dir: Option<PathBuf>,
+    pub tables: Vec<TableMetadata>,
+    pub views: Vec<ViewMetadata>,
+}
+
+/// Bundle the definitions of the given tables and views, or of the whole
+/// catalog when no names are given
+/// Definitions are kept as written, and the bundle records the tables
+/// directory so relative data paths can be rebased when they're imported
+pub fn export_catalog(names: &[String]) -> Result<CatalogBundle> {
+    let names = if names.is_empty() {
+        let mut names = get_tables()?;
+        names.extend(get_views()?);
+        names
+    } else {
+        names.to_vec()
+    };
+    let tables_dir = program::tables_dir()?;
+    let mut bundle = CatalogBundle {
+        tables_dir: Some(
+            tables_dir
+                .canonicalize()
+                .or_else(|_| path::absolute(&tables_dir))?,
+        ),
+        ..Default::default()
+    };
+    for name in names {
+        let table_path = get_path_for_table(&name)?;
+        if table_path.exists() {
+            bundle.tables.push(read_table_definition(&table_path)?);
+        } else if get_path_for_view(&name)?.exists() {
+            bundle.views.push(get_view_metadata_by_name(&name)?);
+        } else {
+            return Err(WingsError::NotFound(format!(
+                "{} isn't a table or view to export.",
+                name
+            )));
+        }
+    }
+    Ok(bundle)
+}
+
+/// Add the tables and views in a bundle to the catalog
+/// Everything is checked before anything is written, so a bundle is imported
+/// whole or not at all. Data paths aren't checked since they're often on
+/// another machine, but relative ones are rebased from the catalog the bundle
+/// came from onto this one.
+pub fn import_catalog(bundle: &CatalogBundle, overwrite: bool) -> Result<()> {
+    let mut names: HashSet<&str> = HashSet::new();
+    for name in bundle
+        .tables
+        .iter()
+        .map(|table| table.metadata.name.as_str())
+        .chain(bundle.views.iter().map(|view| view.name.as_str()))
+    {
+        if !names.insert(name) {
+            return Err(WingsError::Metadata(format!(
+                "{} is in the bundle more than once.",
+                name
+            )));
+        }
+    }
+
+    let mut conflicts: Vec<&str> = Vec::new();
+    for table in &bundle.tables {
+        let name = table.metadata.name.as_str();
+        check_format_is_known(&table.metadata.format)?;
+        table.metadata.options.validate(&table.metadata.format)?;
+        table.validate()?;
+        if get_path_for_view(name)?.exists() {
+            return Err(WingsError::Metadata(format!(
+                "{} is already the name of a view.",
+                name
+            )));
+        }
+        if get_path_for_table(name)?.exists() {
+            conflicts.push(name);
+        }
+    }
+    let mut views: Vec<ViewMetadata> = Vec::new();
+    for view in &bundle.views {
+        check_view_name(&view.name)?;
+        if get_path_for_table(&view.name)?.exists() {
+            return Err(WingsError::Metadata(format!(
+                "{} is already the name of a table.",
+                view.name
+            )));
+        }
+        if get_path_for_view(&view.name)?.exists() {
+            conflicts.push(&view.name);
+        }
+        let query = check_view_query(&view.name, &view.query, &|name| {
+            Ok(names.contains(name)
+                || get_path_for_table(name)?.exists()
+                || get_path_for_view(name)?.exists())
+        })?;
+        views.push(ViewMetadata {
+            name: view.name.clone(),
+            query,
+        });
+    }
+    check_view_cycles(&views)?;
+    if !conflicts.is_empty() && !overwrite {
+        return Err(WingsError::Metadata(format!(
+            "Already in the catalog: {}. Import with --overwrite to replace them.",
+            conflicts.join(", ")
+        )));
+    }
+
+    let tables_dir = program::tables_dir()?;
+    for table in &bundle.tables {
+        let mut table = table.clone();
+        if let Some(source) = bundle.tables_dir.as_deref() {
+            if table.metadata.data_path.is_relative() {
+                // The source directory is resolved already, so its `..`s can
+                // be taken off as text when the data isn't on this machine
+                let data_path = normalize_path(&source.join(&table.metadata.data_path));
+                table.metadata.data_path = relative_to_tables_dir(&data_path, &tables_dir);
+            }
+        }
+        write_table_metadata(&get_path_for_table(&table.metadata.name)?, &table)?;
+    }
+    for view in &views {
+        write_view_metadata(view)?;
+    }
+    Ok(())
+}
+
+/// Check no view reads itself through other views, with the given views
+/// taking the place of any in the catalog with the same name
+fn check_view_cycles(views: &[ViewMetadata]) -> Result<()> {
+    let queries: HashMap<&str, &str> = views
+        .iter()
+        .map(|view| (view.name.as_str(), view.query.as_str()))
+        .collect();
+    for view in views {
+        let mut names = query_parser::get_tables_from_query(&view.query);
+        let mut seen: HashSet<String> = HashSet::new();
+        while let Some(name) = names.pop() {
+            if name == view.name {
+                return Err(WingsError::Metadata(format!(
+                    "View {} reads itself through other views",
+                    view.name
+                )));
+            }
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            let query = match queries.get(name.as_str()) {
+                Some(query) => query.to_string(),
+                None if get_path_for_view(&name)?.exists() => {
+                    get_view_metadata_by_name(&name)?.query
+                }
+                None => continue,
+            };
+            names.extend(query_parser::get_tables_from_query(&query));
+        }
+    }
+    Ok(())
+}
+
+/// Format of a table's files
+///
+/// Stored in the table definition by name. Names that aren't built in are
+/// formats a library user added to the session's [`FormatRegistry`].
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub enum Format {
+    #[default]
     Csv,
+    Avro,
+    Jsonl,
+    Custom(String),
+}
+
+/// Formats built into wings, in the order they're listed
+static BUILT_IN_FORMATS: [Format; 3] = [Format::Csv, Format::Avro, Format::Jsonl];
+
+impl Format {
+    pub fn name(&self) -> &str {
+        match self {
+            Format::Csv => "csv",
+            Format::Avro => "avro",
+            Format::Jsonl => "jsonl",
+            Format::Custom(name) => name,
+        }
+    }
+}
+
+impl From<&str> for Format {
+    /// Built in formats match ignoring case, anything else is a custom format
+    fn from(name: &str) -> Self {
+        BUILT_IN_FORMATS
+            .iter()
+            .find(|format| format.name().eq_ignore_ascii_case(name))
+            .cloned()
+            .unwrap_or_else(|| Format::Custom(name.to_string()))
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Serialize for Format {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl<'de> Deserialize<'de> for Format {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        Ok(Format::from(String::deserialize(deserializer)?.as_str()))
+    }
+}
+
+impl ValueEnum for Format {
+    /// Only the built in formats can be given on the command line
+    fn value_variants<'a>() -> &'a [Self] {
+        &BUILT_IN_FORMATS
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        match self {
+            Format::Csv => Some(PossibleValue::new("csv")),
+            Format::Avro => Some(PossibleValue::new("avro")),
+            Format::Jsonl => Some(PossibleValue::new("jsonl")),
+            Format::Custom(_) => None,
+        }
+    }
+}
+
+/// Options for reading a table's files, under the name of the format they're for
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct FormatOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub csv: Option<CsvOptions>,
+}
+
+impl FormatOptions {
+    pub fn is_empty(&self) -> bool {
+        self.csv.is_none()
+    }
+
+    /// Check the options are valid and are all for the given format
+    pub fn validate(&self, format: &Format) -> Result<()> {
+        if let Some(csv) = &self.csv {
+            if *format != Format::Csv {
+                return Err(WingsError::Metadata(format!(
+                    "CSV options can't be used with format {}",
+                    format
+                )));
+            }
+            csv.validate()?;
+        }
+        Ok(())
+    }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+/// Column holding each row's position in the data when row numbers are on
+pub const ROW_NUMBER_COLUMN: &str = "_row";
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
 pub struct Metadata {
     pub name: String,
-    pub format: String,
+    pub format: Format,
     pub data_path: PathBuf,
+    /// Options for reading the format, like the CSV delimiter
+    #[serde(default, skip_serializing_if = "FormatOptions::is_empty")]
+    pub options: FormatOptions,
+    /// Number rows in file order in the `_row` column
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub row_numbers: bool,
+    /// What to do with rows that break a column constraint
+    #[serde(default, skip_serializing_if = "ErrorPolicy::is_default")]
+    pub on_error: ErrorPolicy,
+    /// Indexes built once the table is loaded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub indexes: Vec<Index>,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Index on a table's columns, built after its rows are loaded so the bulk
+/// insert doesn't have to keep it up to date
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Index {
+    pub columns: Vec<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub unique: bool,
+}
+
+impl Index {
+    /// Name of the index in the database, like `idx_orders_user_id`
+    /// Names made only of letters and digits can't be confused once joined
+    /// with underscores. Anything else is replaced with an underscore and the
+    /// name ends with a hash of the quoted table and columns, after a double
+    /// underscore that plain names never have, so `orders_user(id)` and
+    /// `orders(user_id)` get different names
+    pub fn name(&self, table: &str) -> String {
+        let table = split_table_name(table).1;
+        let parts: Vec<&str> = std::iter::once(table)
+            .chain(self.columns.iter().map(String::as_str))
+            .collect();
+        let readable: Vec<String> = parts
+            .iter()
+            .map(|part| {
+                part.chars()
+                    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                    .collect()
+            })
+            .collect();
+        let name = format!("idx_{}", readable.join("_"));
+        let plain = parts
+            .iter()
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric()));
+        if plain {
+            return name;
+        }
+        let quoted: Vec<String> = parts.iter().map(|part| quote_identifier(part)).collect();
+        format!("{}__{:08x}", name, fnv_hash(&quoted.join(",")) as u32)
+    }
+
+    /// SQL that builds the index unless it's already built
+    /// An index is in its table's namespace, which is given on the index
+    /// rather than the table
+    pub fn to_sql(&self, table: &str) -> String {
+        let columns: Vec<String> = self
+            .columns
+            .iter()
+            .map(|column| quote_identifier(column))
+            .collect();
+        // Column names can have dots, so the name is quoted on its own
+        let name = match split_table_name(table).0 {
+            Some(namespace) => format!(
+                "{}.{}",
+                quote_identifier(namespace),
+                quote_identifier(&self.name(table))
+            ),
+            None => quote_identifier(&self.name(table)),
+        };
+        format!(
+            "CREATE {}INDEX IF NOT EXISTS {} ON {} ({})",
+            if self.unique { "UNIQUE " } else { "" },
+            name,
+            quote_identifier(split_table_name(table).1),
+            columns.join(", ")
+        )
+    }
+}
+
+/// 64-bit FNV-1a hash, which unlike the standard library's hasher gives the
+/// same value in every build, so names made from it stay the same
+fn fnv_hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl fmt::Display for Index {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({})", self.columns.join(", "))?;
+        if self.unique {
+            write!(f, " unique")?;
+        }
+        Ok(())
+    }
+}
+
+/// What loading does with a row that breaks a column constraint
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Default, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorPolicy {
+    /// Stop loading and fail the query
+    #[default]
+    Fail,
+    /// Leave the row out and keep loading
+    Skip,
+}
+
+impl ErrorPolicy {
+    fn is_default(&self) -> bool {
+        *self == ErrorPolicy::default()
+    }
+}
+
+impl fmt::Display for ErrorPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorPolicy::Fail => write!(f, "fail"),
+            ErrorPolicy::Skip => write!(f, "skip"),
+        }
+    }
+}
+
+/// Value a column takes when a row doesn't have one
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(untagged)]
+pub enum DefaultValue {
+    Boolean(bool),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+}
+
+impl DefaultValue {
+    /// The value as a SQL literal
+    pub fn to_sql(&self) -> String {
+        match self {
+            DefaultValue::Boolean(value) => (*value as i64).to_string(),
+            DefaultValue::Integer(value) => value.to_string(),
+            DefaultValue::Float(value) => value.to_string(),
+            DefaultValue::Text(value) => format!("'{}'", value.replace('\'', "''")),
+        }
+    }
+}
+
+/// Constraints on a column's values, checked as rows are loaded
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnConstraints {
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub nullable: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<DefaultValue>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub primary_key: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub unique: bool,
+    /// SQL expression every value has to satisfy, like `amount >= 0`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub check: Option<String>,
+}
+
+impl Default for ColumnConstraints {
+    fn default() -> Self {
+        ColumnConstraints {
+            nullable: true,
+            default: None,
+            primary_key: false,
+            unique: false,
+            check: None,
+        }
+    }
+}
+
+impl ColumnConstraints {
+    pub fn is_empty(&self) -> bool {
+        *self == ColumnConstraints::default()
+    }
+
+    /// Constraint clauses for the column's definition
+    /// A primary key shared by several columns is declared on the table instead
+    fn to_sql(&self, inline_primary_key: bool) -> String {
+        // SQLite lets primary keys other than integer ones be NULL
+        let mut sql = String::from(if self.nullable && !self.primary_key {
+            " NULL"
+        } else {
+            " NOT NULL"
+        });
+        if let Some(default) = &self.default {
+            sql += &format!(" DEFAULT {}", default.to_sql());
+        }
+        if self.primary_key && inline_primary_key {
+            sql += " PRIMARY KEY";
+        }
+        if self.unique {
+            sql += " UNIQUE";
+        }
+        if let Some(check) = &self.check {
+            sql += &format!(" CHECK ({})", check);
+        }
+        sql
+    }
+}
+
+/// A column in a schema yaml, written as just its type or as a mapping of
+/// its type and constraints
+#[derive(PartialEq, Debug, Clone)]
+pub struct Column {
+    pub column_type: String,
+    pub constraints: ColumnConstraints,
+}
+
+impl Serialize for Column {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        if self.constraints.is_empty() {
+            return serializer.serialize_str(&self.column_type);
+        }
+        let mut mapping = serde_yaml::Mapping::new();
+        mapping.insert("type".into(), self.column_type.clone().into());
+        if let serde_yaml::Value::Mapping(constraints) =
+            serde_yaml::to_value(&self.constraints).map_err(serde::ser::Error::custom)?
+        {
+            mapping.extend(constraints);
+        }
+        mapping.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Column {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        match serde_yaml::Value::deserialize(deserializer)? {
+            serde_yaml::Value::String(column_type) => Ok(Column {
+                column_type,
+                constraints: ColumnConstraints::default(),
+            }),
+            serde_yaml::Value::Mapping(mut mapping) => {
+                let column_type = match mapping.remove("type") {
+                    Some(serde_yaml::Value::String(column_type)) => column_type,
+                    _ => return Err(serde::de::Error::custom("column is missing its type")),
+                };
+                let constraints = serde_yaml::from_value(serde_yaml::Value::Mapping(mapping))
+                    .map_err(serde::de::Error::custom)?;
+                Ok(Column {
+                    column_type,
+                    constraints,
+                })
+            }
+            _ => Err(serde::de::Error::custom(
+                "column must be a type or a mapping with a type",
+            )),
+        }
+    }
+}
+
+/// Table definition as it's written in the catalog
+#[derive(Serialize, Deserialize)]
+struct TableDefinition {
+    metadata: Metadata,
+    schema: BTreeMap<String, Column>,
+}
+
+impl From<TableDefinition> for TableMetadata {
+    fn from(definition: TableDefinition) -> Self {
+        let mut table_metadata = TableMetadata {
+            metadata: definition.metadata,
+            schema: BTreeMap::new(),
+            constraints: BTreeMap::new(),
+        };
+        table_metadata.set_columns(definition.schema);
+        table_metadata
+    }
+}
+
+impl From<TableMetadata> for TableDefinition {
+    fn from(mut table_metadata: TableMetadata) -> Self {
+        let schema = table_metadata
+            .schema
+            .into_iter()
+            .map(|(name, column_type)| {
+                let constraints = table_metadata.constraints.remove(&name).unwrap_or_default();
+                (
+                    name,
+                    Column {
+                        column_type,
+                        constraints,
+                    },
+                )
+            })
+            .collect();
+        TableDefinition {
+            metadata: table_metadata.metadata,
+            schema,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(from = "TableDefinition", into = "TableDefinition")]
 pub struct TableMetadata {
     pub metadata: Metadata,
+    /// Column names to types
     pub schema: BTreeMap<String, String>,
+    /// Constraints of the columns that have any
+    pub constraints: BTreeMap<String, ColumnConstraints>,
 }
 
 impl TableMetadata {
+    /// Replace the schema with the given columns and their constraints
+    pub fn set_columns(&mut self, columns: BTreeMap<String, Column>) {
+        self.schema.clear();
+        self.constraints.clear();
+        for (name, column) in columns {
+            if !column.constraints.is_empty() {
+                self.constraints.insert(name.clone(), column.constraints);
+            }
+            self.schema.insert(name, column.column_type);
+        }
+    }
+
+    /// Whether every row in the files ends up in the table
+    /// Constraints can leave rows out or fail the load
+    pub fn loads_every_row(&self) -> bool {
+        self.constraints.is_empty()
+    }
+
+    /// Columns loaded from the data, including `_row` when row numbers are on
+    pub fn columns(&self) -> Vec<String> {
+        let mut columns: Vec<String> = self.schema.keys().cloned().collect();
+        if self.metadata.row_numbers {
+            columns.push(ROW_NUMBER_COLUMN.to_string());
+        }
+        columns
+    }
+
+    /// Apply an alteration to this definition, checking that it makes sense
+    pub fn alter(&mut self, alteration: Alteration) -> Result<()> {
+        match alteration {
+            Alteration::AddColumn { name, column_type } => {
+                self.check_new_column(&name)?;
+                self.schema.insert(name, check_column_type(&column_type)?);
+            }
+            Alteration::DropColumn { name } => {
+                self.check_column(&name)?;
+                if self.schema.len() == 1 {
+                    return Err(WingsError::Schema(format!(
+                        "Can't drop {}, the only column of {}",
+                        name, self.metadata.name
+                    )));
+                }
+                self.schema.remove(&name);
+                self.constraints.remove(&name);
+                // Indexes on the column can't be built any more
+                self.metadata
+                    .indexes
+                    .retain(|index| !index.columns.contains(&name));
+            }
+            Alteration::RenameColumn { name, new_name } => {
+                self.check_column(&name)?;
+                self.check_new_column(&new_name)?;
+                let column_type = self.schema.remove(&name).unwrap_or_default();
+                if let Some(constraints) = self.constraints.remove(&name) {
+                    self.constraints.insert(new_name.clone(), constraints);
+                }
+                for index in self.metadata.indexes.iter_mut() {
+                    for column in index.columns.iter_mut().filter(|column| **column == name) {
+                        *column = new_name.clone();
+                    }
+                }
+                self.schema.insert(new_name, column_type);
+            }
+            Alteration::SetColumnType { name, column_type } => {
+                self.check_column(&name)?;
+                self.schema.insert(name, check_column_type(&column_type)?);
+            }
+            Alteration::SetDataPath(data_path) => {
+                self.metadata.data_path = stored_data_path(&data_path)?;
+            }
+            Alteration::SetFormat(format) => {
+                check_format_is_known(&format)?;
+                // Options for the old format don't apply any more
+                if self.metadata.options.validate(&format).is_err() {
+                    self.metadata.options = FormatOptions::default();
+                }
+                self.metadata.format = format;
+            }
+            Alteration::SetErrorPolicy(on_error) => {
+                self.metadata.on_error = on_error;
+            }
+            Alteration::AddIndex(index) => {
+                if self
+                    .metadata
+                    .indexes
+                    .iter()
+                    .any(|existing| existing.columns == index.columns)
+                {
+                    return Err(WingsError::Schema(format!(
+                        "Table {} already has an index on {}",
+                        self.metadata.name,
+                        index.columns.join(", ")
+                    )));
+                }
+                self.metadata.indexes.push(index);
+            }
+            Alteration::DropIndex(columns) => {
+                let count = self.metadata.indexes.len();
+                self.metadata
+                    .indexes
+                    .retain(|index| index.columns != columns);
+                if self.metadata.indexes.len() == count {
+                    return Err(WingsError::Schema(format!(
+                        "Table {} has no index on {}",
+                        self.metadata.name,
+                        columns.join(", ")
+                    )));
+                }
+            }
+            Alteration::Rename(name) => {
+                if name.trim().is_empty() {
+                    return Err(WingsError::Metadata(
+                        "Table name can't be empty".to_string(),
+                    ));
+                }
+                self.metadata.name = name;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_column(&self, name: &str) -> Result<()> {
+        if self.schema.contains_key(name) {
+            Ok(())
+        } else {
+            Err(WingsError::Schema(format!(
+                "Column {} doesn't exist in {}",
+                name, self.metadata.name
+            )))
+        }
+    }
+
+    fn check_new_column(&self, name: &str) -> Result<()> {
+        check_column_name(name)?;
+        if self.schema.contains_key(name) {
+            Err(WingsError::Schema(format!(
+                "Column {} already exists in {}",
+                name, self.metadata.name
+            )))
+        } else if self.metadata.row_numbers && name == ROW_NUMBER_COLUMN {
+            Err(WingsError::Schema(format!(
+                "Column {} is used for row numbers in {}",
+                name, self.metadata.name
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Check the definition can be created: column types are known, the
+    /// table and column names are valid and not duplicated, and constraints
+    /// and indexes are on columns that exist
+    pub fn validate(&self) -> Result<()> {
+        if self.schema.is_empty() {
+            return Err(WingsError::Schema(format!(
+                "Table {} has no columns",
+                self.metadata.name
+            )));
+        }
+        check_table_name(&self.metadata.name)?;
+        for (column, column_type) in &self.schema {
+            check_column_name(column)?;
+            if !is_known_type(column_type) {
+                return Err(WingsError::Schema(format!(
+                    "Column {} has unknown type {}, expected one of {}",
+                    column,
+                    column_type,
+                    KNOWN_TYPES.join(", ")
+                )));
+            }
+        }
+        if self.metadata.row_numbers && self.schema.contains_key(ROW_NUMBER_COLUMN) {
+            return Err(WingsError::Schema(format!(
+                "Column {} is used for row numbers in {}",
+                ROW_NUMBER_COLUMN, self.metadata.name
+            )));
+        }
+
+        for (column, constraints) in &self.constraints {
+            self.check_column(column)?;
+            // Check expressions are spliced into the table's SQL, so they
+            // mustn't end it early
+            let ends_statement = constraints.check.as_deref().is_some_and(|check| {
+                query_parser::tokenize(check).contains(&query_parser::Token::Symbol(';'))
+            });
+            if ends_statement {
+                return Err(WingsError::Schema(format!(
+                    "Check for column {} must be a single expression",
+                    column
+                )));
+            }
+        }
+
+        let columns = self.columns();
+        for index in &self.metadata.indexes {
+            if index.columns.is_empty() {
+                return Err(WingsError::Schema(format!(
+                    "Index on {} has no columns",
+                    self.metadata.name
+                )));
+            }
+            if let Some(column) = index
+                .columns
+                .iter()
+                .find(|column| !columns.contains(column))
+            {
+                return Err(WingsError::Schema(format!(
+                    "Index {} is on column {}, which doesn't exist in {}",
+                    index, column, self.metadata.name
+                )));
+            }
+        }
+
+        // SQLite knows best which names and expressions it accepts
+        let connection = sqlite::open(":memory:")?;
+        data_loader::attach_namespace(&connection, &self.metadata.name)?;
+        let statements = [schema_to_db(self)].into_iter().chain(
+            self.metadata
+                .indexes
+                .iter()
+                .map(|index| index.to_sql(&self.metadata.name)),
+        );
+        for statement in statements {
+            connection.execute(statement).map_err(|error| {
+                WingsError::Schema(format!(
+                    "Invalid definition for table {}: {}",
+                    self.metadata.name,
+                    error.message.unwrap_or_default()
+                ))
+            })?;
+        }
+        Ok(())
+    }
+
     pub fn table_definition(&self) -> String {
         // For the given schema, turn all entries into column definition
-        let columns: Vec<String> = self.schema.iter().map(|(key, value)| format!("{} {} NULL", key, value)).collect();
+        let primary_key: Vec<String> = self
+            .constraints
+            .iter()
+            .filter(|(_, constraints)| constraints.primary_key)
+            .map(|(column, _)| quote_identifier(column))
+            .collect();
+        let mut columns: Vec<String> = self
+            .schema
+            .iter()
+            .map(|(key, value)| {
+                let constraints = self.constraints.get(key).cloned().unwrap_or_default();
+                format!(
+                    "{} {}{}",
+                    quote_identifier(key),
+                    value,
+                    constraints.to_sql(primary_key.len() == 1)
+                )
+            })
+            .collect();
+        if self.metadata.row_numbers {
+            columns.push(format!("{} int NULL", quote_identifier(ROW_NUMBER_COLUMN)));
+        }
+        if primary_key.len() > 1 {
+            columns.push(format!("PRIMARY KEY ({})", primary_key.join(", ")));
+        }
         // Combine columns
-        let definition = columns.join(",\n");
-        return definition;
+        columns.join(",\n")
+    }
+}
+
+/// Check a catalog table's format is one wings can read
+fn check_format_is_known(format: &Format) -> Result<()> {
+    if FormatRegistry::default().get(format.name()).is_none() {
+        return Err(WingsError::Metadata(format!(
+            "Unknown format {}, expected one of {}",
+            format,
+            FormatRegistry::default().names().join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// Check a table name is a plain SQL identifier, optionally in a namespace
+/// that is one too, like `billing.invoices`
+/// Table names are also catalog file names and are written bare in queries
+fn check_table_name(name: &str) -> Result<()> {
+    let (namespace, table) = split_table_name(name);
+    if !namespace.is_none_or(is_identifier) || !is_identifier(table) {
+        return Err(WingsError::Schema(format!(
+            "{:?} isn't a valid table name, use letters, digits and underscores, with an optional namespace like billing.invoices",
+            name
+        )));
+    }
+    // SQLite already uses these names for its own databases
+    if let Some(namespace) =
+        namespace.filter(|namespace| ["main", "temp"].contains(&namespace.to_lowercase().as_str()))
+    {
+        return Err(WingsError::Schema(format!(
+            "{} can't be used as a namespace",
+            namespace
+        )));
+    }
+    Ok(())
+}
+
+/// Whether a name is letters, digits and underscores, not starting with a digit
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Check a column name can be used, any name is quoted in SQL so only empty
+/// names and control characters are rejected
+fn check_column_name(name: &str) -> Result<()> {
+    if name.trim().is_empty() || name.chars().any(char::is_control) {
+        Err(WingsError::Schema(format!(
+            "{:?} isn't a valid column name",
+            name
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Column types a schema can use
+pub const KNOWN_TYPES: [&str; 18] = [
+    "int",
+    "integer",
+    "bigint",
+    "smallint",
+    "tinyint",
+    "real",
+    "float",
+    "double",
+    "numeric",
+    "decimal",
+    "boolean",
+    "text",
+    "varchar",
+    "char",
+    "blob",
+    "date",
+    "datetime",
+    "timestamp",
+];
+
+/// Whether a column type is known, ignoring case and a size like varchar(255)
+fn is_known_type(column_type: &str) -> bool {
+    let (base, size) = match column_type.split_once('(') {
+        Some((base, size)) => (base, Some(size)),
+        None => (column_type, None),
+    };
+    // A size is one or two numbers, like (255) or (10, 2)
+    let valid_size = size.is_none_or(|size| {
+        size.strip_suffix(')').is_some_and(|numbers| {
+            let numbers: Vec<&str> = numbers.split(',').map(str::trim).collect();
+            numbers.len() <= 2
+                && numbers
+                    .iter()
+                    .all(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+        })
+    });
+    valid_size
+        && KNOWN_TYPES
+            .iter()
+            .any(|known| known.eq_ignore_ascii_case(base.trim()))
+}
+
+/// Check a column type is known
+fn check_column_type(column_type: &str) -> Result<String> {
+    if is_known_type(column_type) {
+        Ok(column_type.to_string())
+    } else {
+        Err(WingsError::Schema(format!(
+            "Unknown column type {}, expected one of {}",
+            column_type,
+            KNOWN_TYPES.join(", ")
+        )))
     }
 }
 
-pub fn get_table_metadata(table_path: PathBuf) -> Result<TableMetadata, SerdeError> {
-    let contents = fs::read_to_string(table_path).expect("Should have been able to read the file");
+/// Read a table definition
+/// A relative data_path is resolved against the tables directory the
+/// definition is in, so catalogs checked into a project work from any
+/// directory. Definitions in a namespace are a directory further down, and
+/// their paths are resolved from the same place.
+pub fn get_table_metadata(table_path: PathBuf) -> Result<TableMetadata> {
+    let contents = fs::read_to_string(&table_path)?;
 
-    let table_metadata: TableMetadata = serde_yaml::from_str(&contents)?;
-    return Ok(table_metadata);
+    let mut table_metadata: TableMetadata = serde_yaml::from_str(&contents)?;
+    if table_metadata.metadata.data_path.is_relative() {
+        let depth = match split_table_name(&table_metadata.metadata.name) {
+            (Some(_), _) => 2,
+            (None, _) => 1,
+        };
+        if let Some(tables_dir) = table_path.ancestors().nth(depth) {
+            table_metadata.metadata.data_path = tables_dir.join(&table_metadata.metadata.data_path);
+        }
+    }
+    Ok(table_metadata)
+}
+
+/// Get the metadata for a table in the catalog by name
+pub fn get_table_metadata_by_name(table: &str) -> Result<TableMetadata> {
+    let table_path = get_path_for_table(table)?;
+    if !table_path.exists() {
+        return Err(WingsError::NotFound(format!(
+            "Table {} doesn't exist. Create it first with `wings create`",
+            table
+        )));
+    }
+    get_table_metadata(table_path)
 }
 
 pub fn create_table_render(table_metadata: TableMetadata) -> String {
-  let columns: Vec<String> = table_metadata.schema.iter().map(|c| {
-    format!("{}, {}", c.0, c.1)
-  }).collect();
-  let column_render = columns.join("\n");
+    let columns: Vec<String> = table_metadata
+        .schema
+        .iter()
+        .map(|c| {
+            let constraints = match table_metadata.constraints.get(c.0) {
+                Some(constraints) => constraints.to_sql(true),
+                None => String::new(),
+            };
+            format!("{}, {}{}", c.0, c.1, constraints)
+        })
+        .collect();
+    let column_render = columns.join("\n");
 
-  return "Name: ".to_owned() + &table_metadata.metadata.name + 
-  "\n--------------------\nMetadata\n" +
-  "Path: " + table_metadata.metadata.data_path.to_str().unwrap() + "\n" +
-  "Format: " + &table_metadata.metadata.format +
-  "\n--------------------\nColumns\n" +
-  &column_render;
+    "Name: ".to_owned()
+        + &table_metadata.metadata.name
+        + "\n--------------------\nMetadata\n"
+        + "Path: "
+        + &table_metadata.metadata.data_path.to_string_lossy()
+        + "\n"
+        + "Format: "
+        + table_metadata.metadata.format.name()
+        + &match &table_metadata.metadata.options.csv {
+            Some(csv) => format!(
+                "\nCSV: delimiter {:?}, quote {:?}, headers {}",
+                csv.delimiter, csv.quote, csv.has_headers
+            ),
+            None => String::new(),
+        }
+        + if table_metadata.metadata.row_numbers {
+            "\nRow numbers: _row"
+        } else {
+            ""
+        }
+        + &format!("\nOn error: {}", table_metadata.metadata.on_error)
+        + &table_metadata
+            .metadata
+            .indexes
+            .iter()
+            .map(|index| format!("\nIndex: {}", index))
+            .collect::<String>()
+        + "\n--------------------\nColumns\n"
+        + &column_render
 }
 
-pub fn get_metadata_for_display(name: &String) -> Result<String, Error> {
-    let path = get_path_for_table(name);
+pub fn get_metadata_for_display(name: &str) -> Result<String> {
+    let path = get_path_for_table(name)?;
     if path.exists() {
-        let metadata_res = get_table_metadata(path);
-        match metadata_res {
-            Ok(table_metadata) => {
-                let render = create_table_render(table_metadata);
-                return Ok(render);
-            },
-            Err(err) => panic!("{}", err),
-        }
+        let table_metadata = get_table_metadata(path)?;
+        Ok(create_table_render(table_metadata))
     } else {
-        let error = Error::new(ErrorKind::InvalidInput, format!("Can't find table {}", name));
-        Err(error)
+        Err(WingsError::NotFound(format!("Can't find table {}", name)))
     }
 }
 
-pub fn render_tables() {
+pub fn render_views() -> Result<()> {
+    let views = get_views()?;
+    println!("Views:");
+    if views.is_empty() {
+        println!("No views found");
+    }
+    for view in views {
+        println!("{}: {}", view, get_view_metadata_by_name(&view)?.query);
+    }
+    Ok(())
+}
+
+/// Print every table, grouped by namespace
+pub fn render_tables() -> Result<()> {
+    let tables: Vec<String> = get_tables()?;
     println!("Tables:");
-    let tables: Vec<String> = get_tables();
-    if tables.len() > 0 {
-        for table in tables {
-            println!("{}", table);
-        }
-    } else {
+    if tables.is_empty() {
         println!("No tables found");
     }
+    let mut namespaces: BTreeMap<Option<&str>, Vec<&str>> = BTreeMap::new();
+    for table in &tables {
+        let (namespace, name) = split_table_name(table);
+        namespaces.entry(namespace).or_default().push(name);
+    }
+    for (namespace, names) in namespaces {
+        match namespace {
+            Some(namespace) => {
+                println!("{}:", namespace);
+                for name in names {
+                    println!("  {}", name);
+                }
+            }
+            None => {
+                for name in names {
+                    println!("{}", name);
+                }
+            }
+        }
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{
-        collections::BTreeMap,
-        path::{Path, PathBuf},
-    };
+    use std::{collections::BTreeMap, env, fs, path::PathBuf};
 
-    use crate::metadata::{Format, TableMetadata};
+    use crate::metadata::{
+        get_table_metadata, Alteration, ErrorPolicy, Format, Index, TableMetadata,
+    };
 
-    use super::Metadata;
+    use super::{
+        check_view_cycles, export_catalog, get_path_for_table, get_table_metadata_by_name,
+        import_catalog, parse_schema, read_table_definition, relative_to_tables_dir,
+        remove_empty_namespace, write_table_metadata, CatalogBundle, Metadata, ViewMetadata,
+    };
+    use crate::error::WingsError;
+    use crate::program;
 
     #[test]
     fn table_metadata_definition() {
         let mut schema: BTreeMap<String, String> = BTreeMap::new();
         schema.insert("id".to_string(), "int".to_string());
         schema.insert("name".to_string(), "text".to_string());
-        let mut metadata: BTreeMap<String, String> = BTreeMap::new();
         let table_metadata = TableMetadata {
             metadata: Metadata {
                 name: "test".to_string(),
-                format: "csv".to_string(),
+                format: Format::Csv,
                 data_path: PathBuf::new(),
+                ..Default::default()
             },
             schema,
+            constraints: BTreeMap::new(),
         };
 
-        let expected_result = "id int null\nname text null".to_string();
+        let expected_result = "\"id\" int NULL,\n\"name\" text NULL".to_string();
         assert_eq!(table_metadata.table_definition(), expected_result)
     }
+
+    #[test]
+    fn alter_columns() {
+        let mut table_metadata = TableMetadata {
+            metadata: Metadata {
+                name: "test".to_string(),
+                format: Format::Csv,
+                ..Default::default()
+            },
+            schema: [("id".to_string(), "int".to_string())].into(),
+            constraints: BTreeMap::new(),
+        };
+        table_metadata
+            .alter(Alteration::AddColumn {
+                name: "name".to_string(),
+                column_type: "text".to_string(),
+            })
+            .unwrap();
+        table_metadata
+            .alter(Alteration::RenameColumn {
+                name: "id".to_string(),
+                new_name: "user_id".to_string(),
+            })
+            .unwrap();
+        assert_eq!(
+            table_metadata.table_definition(),
+            "\"name\" text NULL,\n\"user_id\" int NULL"
+        );
+
+        assert!(table_metadata
+            .alter(Alteration::DropColumn {
+                name: "id".to_string()
+            })
+            .is_err());
+        assert!(table_metadata
+            .alter(Alteration::SetFormat(Format::Custom("parquet".to_string())))
+            .is_err());
+        assert!(table_metadata
+            .alter(Alteration::AddColumn {
+                name: "name".to_string(),
+                column_type: "text".to_string(),
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn validate_schema() {
+        let table = |schema: &[(&str, &str)]| TableMetadata {
+            metadata: Metadata {
+                name: "test".to_string(),
+                ..Default::default()
+            },
+            schema: schema
+                .iter()
+                .map(|(column, column_type)| (column.to_string(), column_type.to_string()))
+                .collect(),
+            constraints: BTreeMap::new(),
+        };
+        assert!(table(&[("id", "int"), ("name", "VARCHAR(20)")])
+            .validate()
+            .is_ok());
+        assert!(table(&[("id", "banana")]).validate().is_err());
+        assert!(table(&[("id", "int"), ("ID", "int")]).validate().is_err());
+        assert!(table(&[("user id", "int"), ("end", "text")])
+            .validate()
+            .is_ok());
+        assert!(table(&[("", "int")]).validate().is_err());
+
+        let mut namespaced = table(&[("id", "int")]);
+        namespaced.metadata.name = "billing.invoices".to_string();
+        assert!(namespaced.validate().is_ok());
+        for name in ["main.invoices", "billing.invoices.2024", "billing."] {
+            namespaced.metadata.name = name.to_string();
+            assert!(namespaced.validate().is_err());
+        }
+        assert!(table(&[]).validate().is_err());
+
+        let mut indexed = table(&[("id", "int")]);
+        indexed.metadata.indexes = vec![Index {
+            columns: vec!["missing".to_string()],
+            unique: false,
+        }];
+        assert!(indexed.validate().is_err());
+        indexed
+            .alter(Alteration::DropIndex(vec!["missing".to_string()]))
+            .unwrap();
+        indexed
+            .alter(Alteration::AddIndex(Index {
+                columns: vec!["id".to_string()],
+                unique: true,
+            }))
+            .unwrap();
+        assert!(indexed.validate().is_ok());
+    }
+
+    #[test]
+    fn schema_from_command_line() {
+        let columns =
+            parse_schema("id: {type: int, primary_key: true}, \"user name\": text").unwrap();
+        assert_eq!(columns["id"].column_type, "int");
+        assert!(columns["id"].constraints.primary_key);
+        assert_eq!(columns["user name"].column_type, "text");
+
+        let columns = parse_schema("./examples/schema.yaml").unwrap();
+        assert_eq!(columns.len(), 7);
+        assert!(matches!(
+            parse_schema("./examples/missing.yaml"),
+            Err(WingsError::Schema(_))
+        ));
+    }
+
+    #[test]
+    fn index_names() {
+        let index = |columns: &[&str]| Index {
+            columns: columns.iter().map(|column| column.to_string()).collect(),
+            unique: false,
+        };
+        assert_ne!(
+            index(&["id"]).name("orders_user"),
+            index(&["user_id"]).name("orders")
+        );
+        assert_ne!(
+            index(&["a,b"]).name("orders"),
+            index(&["a", "b"]).name("orders")
+        );
+        assert_eq!(
+            index(&["id", "createdAt"]).name("billing.orders"),
+            "idx_orders_id_createdAt"
+        );
+        assert_eq!(
+            index(&["user.id"]).to_sql("billing.orders"),
+            r#"CREATE INDEX IF NOT EXISTS "billing"."idx_orders_user_id__553c20f8" ON "orders" ("user.id")"#
+        );
+    }
+
+    #[test]
+    fn column_constraints() {
+        let yaml = "metadata:
+  name: orders
+  format: csv
+  data_path: /data/orders
+  on_error: skip
+schema:
+  id:
+    type: int
+    primary_key: true
+  amount:
+    type: real
+    nullable: false
+    default: 0
+    check: amount >= 0
+  note: text
+";
+        let table_metadata: TableMetadata = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(table_metadata.metadata.on_error, ErrorPolicy::Skip);
+        assert_eq!(table_metadata.schema.get("note").unwrap(), "text");
+        assert_eq!(
+            table_metadata.table_definition(),
+            "\"amount\" real NOT NULL DEFAULT 0 CHECK (amount >= 0),\n\"id\" int NOT NULL PRIMARY KEY,\n\"note\" text NULL"
+        );
+        assert!(table_metadata.validate().is_ok());
+        // Columns without constraints are still written as just their type
+        let written = serde_yaml::to_string(&table_metadata).unwrap();
+        assert!(written.contains("note: text"));
+        assert_eq!(
+            serde_yaml::from_str::<TableMetadata>(&written).unwrap(),
+            table_metadata
+        );
+
+        let mut broken = table_metadata.clone();
+        broken.constraints.get_mut("amount").unwrap().check =
+            Some("amount > 0); drop table x; --".to_string());
+        assert!(broken.validate().is_err());
+        broken.constraints.get_mut("amount").unwrap().check = Some("missing > 0".to_string());
+        assert!(broken.validate().is_err());
+        assert!(
+            serde_yaml::from_str::<TableMetadata>(&yaml.replace("nullable", "nulable")).is_err()
+        );
+    }
+
+    #[test]
+    fn relative_data_path_from_definition() {
+        let dir = env::temp_dir().join(format!("wings-definition-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let table_path = dir.join("events.yaml");
+        fs::write(
+            &table_path,
+            "metadata:\n  name: events\n  format: csv\n  data_path: ../data\nschema:\n  id: int\n",
+        )
+        .unwrap();
+
+        // A namespace doesn't change where paths are resolved from
+        let namespaced_path = dir.join("billing").join("events.yaml");
+        fs::create_dir_all(dir.join("billing")).unwrap();
+        fs::write(
+            &namespaced_path,
+            "metadata:\n  name: billing.events\n  format: csv\n  data_path: ../data\nschema:\n  id: int\n",
+        )
+        .unwrap();
+
+        let table_metadata = get_table_metadata(table_path).unwrap();
+        let namespaced = get_table_metadata(namespaced_path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(table_metadata.metadata.data_path, dir.join("../data"));
+        assert_eq!(table_metadata.metadata.format, Format::Csv);
+        assert_eq!(namespaced.metadata.data_path, dir.join("../data"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn relative_data_path_through_symlink() {
+        let dir = env::temp_dir().join(format!("wings-symlink-{}", std::process::id()));
+        let project = dir.join("project");
+        fs::create_dir_all(project.join("data")).unwrap();
+        fs::create_dir_all(dir.join("elsewhere").join("tables")).unwrap();
+        // The project's .wings is a link to a catalog kept somewhere else
+        std::os::unix::fs::symlink(dir.join("elsewhere"), project.join(".wings")).unwrap();
+        let tables_dir = project.join(".wings").join("tables");
+
+        let stored = relative_to_tables_dir(&project.join("data").join("*.csv"), &tables_dir);
+        fs::write(
+            tables_dir.join("events.yaml"),
+            format!(
+                "metadata:\n  name: events\n  format: csv\n  data_path: {}\nschema:\n  id: int\n",
+                stored.display()
+            ),
+        )
+        .unwrap();
+        let table_metadata = get_table_metadata(tables_dir.join("events.yaml")).unwrap();
+        let missing = relative_to_tables_dir(&project.join("missing"), &tables_dir);
+        let read_from = table_metadata
+            .metadata
+            .data_path
+            .parent()
+            .unwrap()
+            .canonicalize();
+        let expected = project.join("data").canonicalize();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(stored.is_relative());
+        assert_eq!(read_from.unwrap(), expected.unwrap());
+        assert_eq!(missing, project.join("missing"));
+    }
+
+    #[test]
+    fn empty_namespace_removed() {
+        let dir = env::temp_dir().join(format!("wings-namespace-{}", std::process::id()));
+        let namespace = dir.join("billing");
+        fs::create_dir_all(&namespace).unwrap();
+        fs::write(namespace.join("events.yaml"), "").unwrap();
+
+        // Still has a table
+        remove_empty_namespace("billing.invoices", &namespace.join("invoices.yaml")).unwrap();
+        assert!(namespace.exists());
+
+        fs::remove_file(namespace.join("events.yaml")).unwrap();
+        remove_empty_namespace("billing.events", &namespace.join("events.yaml")).unwrap();
+        assert!(!namespace.exists());
+
+        // Tables outside a namespace live in the tables directory itself
+        remove_empty_namespace("events", &dir.join("events.yaml")).unwrap();
+        assert!(dir.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn catalog_bundle() {
+        // Views the bundle's views read are looked for in the catalog
+        program::set_test_catalog_dir(
+            env::temp_dir().join(format!("wings-bundle-{}", std::process::id())),
+        );
+        let yaml = "tables:
+- metadata:
+    name: billing.events
+    format: jsonl
+    data_path: data/events
+  schema:
+    id:
+      type: int
+      primary_key: true
+    level: text
+views:
+- name: errors
+  query: select id from billing.events where level = 'error'
+";
+        let bundle: CatalogBundle = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(bundle.tables[0].metadata.name, "billing.events");
+        assert_eq!(
+            bundle.tables[0].metadata.data_path,
+            PathBuf::from("data/events")
+        );
+        assert!(bundle.tables[0].validate().is_ok());
+        assert_eq!(serde_yaml::to_string(&bundle).unwrap(), yaml);
+        assert!(serde_yaml::from_str::<CatalogBundle>("views: []")
+            .unwrap()
+            .tables
+            .is_empty());
+        assert!(serde_yaml::from_str::<CatalogBundle>("tablez: []").is_err());
+
+        assert!(check_view_cycles(&bundle.views).is_ok());
+        let view = |name: &str, query: &str| ViewMetadata {
+            name: name.to_string(),
+            query: query.to_string(),
+        };
+        let cycle = [
+            view("first", "select * from second"),
+            view("second", "select * from third join first using (id)"),
+            view("third", "select 1 as id"),
+        ];
+        assert!(check_view_cycles(&cycle).is_err());
+    }
+
+    #[test]
+    fn import_into_another_catalog() {
+        let dir = env::temp_dir().join(format!("wings-import-{}", std::process::id()));
+        let data = dir.join("project").join("data");
+        fs::create_dir_all(&data).unwrap();
+        let source = dir.join("project").join(".wings");
+        let target = dir.join("elsewhere").join("nested").join(".wings");
+
+        program::set_test_catalog_dir(source.clone());
+        program::init().unwrap();
+        let mut table_metadata: TableMetadata = serde_yaml::from_str(
+            "metadata:\n  name: billing.events\n  format: csv\n  data_path: .\nschema:\n  id: int\n",
+        )
+        .unwrap();
+        table_metadata.metadata.data_path = relative_to_tables_dir(&data, &source.join("tables"));
+        write_table_metadata(
+            &get_path_for_table("billing.events").unwrap(),
+            &table_metadata,
+        )
+        .unwrap();
+        let bundle = export_catalog(&[]).unwrap();
+
+        program::set_test_catalog_dir(target.clone());
+        program::init().unwrap();
+        import_catalog(&bundle, false).unwrap();
+        let imported = get_table_metadata_by_name("billing.events").unwrap();
+        let stored = read_table_definition(&get_path_for_table("billing.events").unwrap())
+            .unwrap()
+            .metadata
+            .data_path;
+        let read_from = imported.metadata.data_path.canonicalize();
+        let expected = data.canonicalize();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(stored.is_relative());
+        assert_eq!(read_from.unwrap(), expected.unwrap());
+    }
 }
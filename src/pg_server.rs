@@ -0,0 +1,1046 @@
+use std::{
+    collections::HashMap,
+    io::{BufReader, BufWriter, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use sqlite::Value;
+
+use crate::error::{Result, WingsError};
+use crate::query_parser::{self, Token};
+use crate::session::{value_to_string, QueryResult, Wings};
+
+/// Protocol version 3.0 sent in the startup message
+const PROTOCOL_VERSION: i32 = 196608;
+/// Request codes sent in place of a protocol version
+const SSL_REQUEST: i32 = 80877103;
+const GSSENC_REQUEST: i32 = 80877104;
+const CANCEL_REQUEST: i32 = 80877102;
+
+/// Largest message accepted from a client, in bytes
+const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Postgres wire protocol server answering queries against the catalog
+///
+/// Each client gets its own thread, but they share one session so tables
+/// loaded by any client stay warm for all of them. Clients are trusted
+/// without a password, so the server only listens on localhost.
+pub struct PgServer {
+    wings: Arc<Mutex<Wings>>,
+}
+
+impl PgServer {
+    pub fn new(wings: Wings) -> PgServer {
+        PgServer {
+            wings: Arc::new(Mutex::new(wings)),
+        }
+    }
+
+    /// Accept connections on localhost until the process is stopped
+    pub fn serve(&self, port: u16) -> Result<()> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        log::info!("Listening on postgresql://{}", listener.local_addr()?);
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let wings = self.wings.clone();
+                    thread::spawn(move || {
+                        if let Err(error) = handle_connection(stream, wings) {
+                            log::warn!("Connection closed with error: {}", error);
+                        }
+                    });
+                }
+                Err(error) => log::warn!("Failed to accept connection: {}", error),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn handle_connection(stream: TcpStream, wings: Arc<Mutex<Wings>>) -> Result<()> {
+    let mut reader = BufReader::new(&stream);
+    let writer = BufWriter::new(&stream);
+    let mut client = Client {
+        wings,
+        writer,
+        statements: HashMap::new(),
+        portals: HashMap::new(),
+    };
+    if !client.startup(&mut reader)? {
+        return Ok(());
+    }
+
+    // After an error in an extended query, messages are skipped until Sync
+    let mut skipping = false;
+    while let Some((tag, payload)) = read_message(&mut reader)? {
+        if skipping && tag != b'S' {
+            continue;
+        }
+        skipping = false;
+        let result = match tag {
+            b'Q' => client.simple_query(&mut Fields::new(&payload)),
+            b'P' => client.parse(&mut Fields::new(&payload)),
+            b'B' => client.bind(&mut Fields::new(&payload)),
+            b'D' => client.describe(&mut Fields::new(&payload)),
+            b'E' => client.execute(&mut Fields::new(&payload)),
+            b'C' => client.close(&mut Fields::new(&payload)),
+            b'S' => client.ready(),
+            b'H' => client.writer.flush().map_err(WingsError::from),
+            b'X' => return Ok(()),
+            _ => Err(WingsError::Protocol(format!(
+                "Unsupported message type {}",
+                tag as char
+            ))),
+        };
+        if let Err(error) = result {
+            client.error(&error)?;
+            if tag == b'Q' {
+                client.ready()?;
+            } else {
+                skipping = true;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Statement created by a Parse message
+struct Prepared {
+    query: String,
+    /// Types the client gave for each parameter, 0 when unspecified
+    param_types: Vec<i32>,
+    /// Column types sent to the client when the statement was described
+    described: Option<Vec<PgType>>,
+}
+
+/// Statement bound to parameters by a Bind message, ready to execute
+struct Portal {
+    query: String,
+    params: Vec<Value>,
+    result_formats: Vec<i16>,
+    /// Column types sent to the client when the statement was described
+    types: Option<Vec<PgType>>,
+    result: Option<QueryResult>,
+    /// Rows already sent when Execute asked for a limited number of rows
+    sent: usize,
+}
+
+struct Client<'a> {
+    wings: Arc<Mutex<Wings>>,
+    writer: BufWriter<&'a TcpStream>,
+    statements: HashMap<String, Prepared>,
+    portals: HashMap<String, Portal>,
+}
+
+impl Client<'_> {
+    /// Answer the startup message, returning false if the client went away
+    fn startup(&mut self, reader: &mut impl Read) -> Result<bool> {
+        loop {
+            let Some(payload) = read_startup(reader)? else {
+                return Ok(false);
+            };
+            let mut fields = Fields::new(&payload);
+            match fields.i32()? {
+                // Encryption isn't supported, so clients carry on in plain text
+                SSL_REQUEST | GSSENC_REQUEST => {
+                    self.writer.write_all(b"N")?;
+                    self.writer.flush()?;
+                }
+                CANCEL_REQUEST => return Ok(false),
+                PROTOCOL_VERSION => break,
+                version => {
+                    return Err(WingsError::Protocol(format!(
+                        "Unsupported protocol version {}",
+                        version
+                    )))
+                }
+            }
+        }
+
+        // Authentication ok
+        self.send(b'R', &Payload::new().i32(0))?;
+        for (name, value) in [
+            ("server_version", "14.0 (wings)"),
+            ("server_encoding", "UTF8"),
+            ("client_encoding", "UTF8"),
+            ("DateStyle", "ISO, MDY"),
+            ("integer_datetimes", "on"),
+            ("standard_conforming_strings", "on"),
+        ] {
+            self.send(b'S', &Payload::new().str(name).str(value))?;
+        }
+        self.send(b'K', &Payload::new().i32(std::process::id() as i32).i32(0))?;
+        self.ready()?;
+        Ok(true)
+    }
+
+    fn ready(&mut self) -> Result<()> {
+        self.send(b'Z', &Payload::new().byte(b'I'))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn error(&mut self, error: &WingsError) -> Result<()> {
+        let code = match error {
+            WingsError::NotFound(_) => "42P01",
+            WingsError::Sql(error) if is_missing_table(error) => "42P01",
+            WingsError::Parse(_) | WingsError::Sql(_) => "42601",
+            WingsError::Protocol(_) => "08P01",
+            _ => "XX000",
+        };
+        let payload = Payload::new()
+            .byte(b'S')
+            .str("ERROR")
+            .byte(b'V')
+            .str("ERROR")
+            .byte(b'C')
+            .str(code)
+            .byte(b'M')
+            .str(&error.to_string())
+            .byte(0);
+        self.send(b'E', &payload)
+    }
+
+    fn send(&mut self, tag: u8, payload: &Payload) -> Result<()> {
+        self.writer.write_all(&[tag])?;
+        self.writer
+            .write_all(&(payload.0.len() as i32 + 4).to_be_bytes())?;
+        self.writer.write_all(&payload.0)?;
+        Ok(())
+    }
+
+    /// Run every statement in a Query message, sending all of their rows
+    fn simple_query(&mut self, fields: &mut Fields) -> Result<()> {
+        let statements = split_statements(&fields.str()?);
+        if statements.is_empty() {
+            self.send(b'I', &Payload::new())?;
+        }
+        for query in statements {
+            if let Some(tag) = ignored_command(&query) {
+                self.send(b'C', &Payload::new().str(tag))?;
+                continue;
+            }
+            let result = self.run(&query, &[])?;
+            let types = column_types(&result);
+            if !result.columns().is_empty() {
+                self.row_description(result.columns(), &types, &[])?;
+            }
+            self.data_rows(&result, &types, &[], 0, result.len())?;
+            self.command_complete(&query, &result)?;
+        }
+        self.ready()
+    }
+
+    fn parse(&mut self, fields: &mut Fields) -> Result<()> {
+        let name = fields.str()?;
+        let query = fields.str()?;
+        let mut param_types = Vec::new();
+        for _ in 0..fields.i16()? {
+            param_types.push(fields.i32()?);
+        }
+        let count = parameter_count(&query).max(param_types.len());
+        param_types.resize(count, 0);
+        self.statements.insert(
+            name,
+            Prepared {
+                query,
+                param_types,
+                described: None,
+            },
+        );
+        self.send(b'1', &Payload::new())
+    }
+
+    fn bind(&mut self, fields: &mut Fields) -> Result<()> {
+        let portal = fields.str()?;
+        let statement_name = fields.str()?;
+        let statement = self.statements.get(&statement_name).ok_or_else(|| {
+            WingsError::Protocol(format!(
+                "Prepared statement {} doesn't exist",
+                statement_name
+            ))
+        })?;
+
+        let param_formats = fields.formats()?;
+        let mut params = Vec::new();
+        for index in 0..fields.i16()? as usize {
+            let binary = format_code(&param_formats, index) == 1;
+            let param_type = statement.param_types.get(index).copied().unwrap_or(0);
+            params.push(match fields.value()? {
+                Some(bytes) => decode_param(bytes, param_type, binary)?,
+                None => Value::Null,
+            });
+        }
+        let result_formats = fields.formats()?;
+
+        let portal_state = Portal {
+            query: statement.query.clone(),
+            params,
+            result_formats,
+            types: statement.described.clone(),
+            result: None,
+            sent: 0,
+        };
+        self.portals.insert(portal, portal_state);
+        self.send(b'2', &Payload::new())
+    }
+
+    fn describe(&mut self, fields: &mut Fields) -> Result<()> {
+        let kind = fields.byte()?;
+        let name = fields.str()?;
+        if kind == b'S' {
+            let statement = self.statements.get(&name).ok_or_else(|| {
+                WingsError::Protocol(format!("Prepared statement {} doesn't exist", name))
+            })?;
+            let query = statement.query.clone();
+            // Parameters are sent as text unless the client asked for a type
+            let param_types: Vec<i32> = statement
+                .param_types
+                .iter()
+                .map(|param_type| {
+                    if *param_type == 0 {
+                        PgType::Text.oid()
+                    } else {
+                        *param_type
+                    }
+                })
+                .collect();
+            let mut payload = Payload::new().i16(param_types.len() as i16);
+            for param_type in param_types {
+                payload = payload.i32(param_type);
+            }
+            self.send(b't', &payload)?;
+
+            if ignored_command(&query).is_some() {
+                return self.send(b'n', &Payload::new());
+            }
+            // Column types come from the values a statement returns, so a
+            // statement described before it runs sends every column as text
+            let columns = self.columns(&query)?;
+            let types = vec![PgType::Text; columns.len()];
+            self.row_description(&columns, &types, &[])?;
+            if let Some(statement) = self.statements.get_mut(&name) {
+                statement.described = Some(types);
+            }
+            Ok(())
+        } else {
+            let portal = self
+                .portals
+                .get(&name)
+                .ok_or_else(|| WingsError::Protocol(format!("Portal {} doesn't exist", name)))?;
+            if ignored_command(&portal.query).is_some() {
+                return self.send(b'n', &Payload::new());
+            }
+            let result = match &portal.result {
+                Some(result) => result.clone(),
+                None => {
+                    let (query, params) = (portal.query.clone(), portal.params.clone());
+                    self.run(&query, &params)?
+                }
+            };
+            let portal = self.portals.get_mut(&name).unwrap();
+            let types = portal
+                .types
+                .clone()
+                .unwrap_or_else(|| column_types(&result));
+            let result_formats = portal.result_formats.clone();
+            portal.types = Some(types.clone());
+            portal.result = Some(result.clone());
+            self.row_description(result.columns(), &types, &result_formats)
+        }
+    }
+
+    fn execute(&mut self, fields: &mut Fields) -> Result<()> {
+        let name = fields.str()?;
+        let max_rows = fields.i32()?;
+        let portal = self
+            .portals
+            .get_mut(&name)
+            .ok_or_else(|| WingsError::Protocol(format!("Portal {} doesn't exist", name)))?;
+        if let Some(tag) = ignored_command(&portal.query) {
+            return self.send(b'C', &Payload::new().str(tag));
+        }
+
+        // The rows are moved out while they're sent and back in after, so
+        // fetching a large result a few rows at a time doesn't copy it
+        let (query, formats, start) = (
+            portal.query.clone(),
+            portal.result_formats.clone(),
+            portal.sent,
+        );
+        let result = match portal.result.take() {
+            Some(result) => result,
+            None => {
+                let params = portal.params.clone();
+                self.run(&query, &params)?
+            }
+        };
+        let portal = self.portals.get_mut(&name).unwrap();
+        let types = portal
+            .types
+            .clone()
+            .unwrap_or_else(|| column_types(&result));
+        let end = if max_rows > 0 {
+            (start + max_rows as usize).min(result.len())
+        } else {
+            result.len()
+        };
+        portal.sent = end;
+
+        self.data_rows(&result, &types, &formats, start, end)?;
+        if end < result.len() {
+            self.send(b's', &Payload::new())?;
+        } else {
+            self.command_complete(&query, &result)?;
+        }
+        self.portals.get_mut(&name).unwrap().result = Some(result);
+        Ok(())
+    }
+
+    fn close(&mut self, fields: &mut Fields) -> Result<()> {
+        let kind = fields.byte()?;
+        let name = fields.str()?;
+        if kind == b'S' {
+            self.statements.remove(&name);
+        } else {
+            self.portals.remove(&name);
+        }
+        self.send(b'3', &Payload::new())
+    }
+
+    /// Run a query on the shared session, registering catalog tables and
+    /// views it references
+    fn run(&mut self, query: &str, params: &[Value]) -> Result<QueryResult> {
+        let mut wings = self.wings.lock().unwrap();
+        wings.register_from_catalog(query)?;
+        wings.query_with_params(query, params)
+    }
+
+    /// Columns a statement returns without running it, registering catalog
+    /// tables and views it references
+    fn columns(&mut self, query: &str) -> Result<Vec<String>> {
+        let mut wings = self.wings.lock().unwrap();
+        wings.register_from_catalog(query)?;
+        wings.columns(query)
+    }
+
+    fn row_description(
+        &mut self,
+        columns: &[String],
+        types: &[PgType],
+        formats: &[i16],
+    ) -> Result<()> {
+        if columns.is_empty() {
+            return self.send(b'n', &Payload::new());
+        }
+        let mut payload = Payload::new().i16(columns.len() as i16);
+        for (index, column) in columns.iter().enumerate() {
+            let pg_type = types.get(index).copied().unwrap_or(PgType::Text);
+            payload = payload
+                .str(column)
+                // Table oid and column number
+                .i32(0)
+                .i16(0)
+                .i32(pg_type.oid())
+                .i16(pg_type.size())
+                // Type modifier
+                .i32(-1)
+                .i16(format_code(formats, index));
+        }
+        self.send(b'T', &payload)
+    }
+
+    fn data_rows(
+        &mut self,
+        result: &QueryResult,
+        types: &[PgType],
+        formats: &[i16],
+        start: usize,
+        end: usize,
+    ) -> Result<()> {
+        for row in &result.rows()[start..end] {
+            let mut payload = Payload::new().i16(row.values().len() as i16);
+            for (index, value) in row.values().iter().enumerate() {
+                let pg_type = types.get(index).copied().unwrap_or(PgType::Text);
+                let binary = format_code(formats, index) == 1;
+                payload = match encode_value(value, pg_type, binary)? {
+                    Some(bytes) => payload.i32(bytes.len() as i32).bytes(&bytes),
+                    None => payload.i32(-1),
+                };
+            }
+            self.send(b'D', &payload)?;
+        }
+        Ok(())
+    }
+
+    fn command_complete(&mut self, query: &str, result: &QueryResult) -> Result<()> {
+        let tag = if result.columns().is_empty() {
+            query_parser::tokenize(query)
+                .first()
+                .and_then(Token::name)
+                .unwrap_or_default()
+                .to_uppercase()
+        } else {
+            format!("SELECT {}", result.len())
+        };
+        self.send(b'C', &Payload::new().str(&tag))
+    }
+}
+
+/// Commands clients send to set up their session that have no meaning for
+/// wings, answered with their command tag without running them
+fn ignored_command(query: &str) -> Option<&'static str> {
+    let first = query_parser::tokenize(query)
+        .into_iter()
+        .next()?
+        .name()?
+        .to_lowercase();
+    match first.as_str() {
+        "set" => Some("SET"),
+        "reset" => Some("RESET"),
+        "discard" => Some("DISCARD ALL"),
+        _ => None,
+    }
+}
+
+/// Postgres types results are sent as
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PgType {
+    Int8,
+    Float8,
+    Text,
+}
+
+impl PgType {
+    fn oid(self) -> i32 {
+        match self {
+            PgType::Int8 => 20,
+            PgType::Float8 => 701,
+            PgType::Text => 25,
+        }
+    }
+
+    fn size(self) -> i16 {
+        match self {
+            PgType::Int8 | PgType::Float8 => 8,
+            PgType::Text => -1,
+        }
+    }
+}
+
+/// Whether SQLite rejected a statement for reading a table that doesn't exist
+fn is_missing_table(error: &sqlite::Error) -> bool {
+    error
+        .message
+        .as_deref()
+        .is_some_and(|message| message.starts_with("no such table"))
+}
+
+/// Pick a type for each result column from the values it holds
+/// SQLite columns can mix types, so anything that isn't all numbers is text
+fn column_types(result: &QueryResult) -> Vec<PgType> {
+    (0..result.columns().len())
+        .map(|index| {
+            let mut pg_type: Option<PgType> = None;
+            for row in result.rows() {
+                pg_type = match (&row.values()[index], pg_type) {
+                    (Value::Null, pg_type) => pg_type,
+                    (Value::Integer(_), None) => Some(PgType::Int8),
+                    (Value::Integer(_), pg_type) => pg_type,
+                    (Value::Float(_), None | Some(PgType::Int8)) => Some(PgType::Float8),
+                    (Value::Float(_), pg_type) => pg_type,
+                    _ => return PgType::Text,
+                };
+            }
+            pg_type.unwrap_or(PgType::Text)
+        })
+        .collect()
+}
+
+/// Encode a value as the column's type in text or binary format, None for NULL
+fn encode_value(value: &Value, pg_type: PgType, binary: bool) -> Result<Option<Vec<u8>>> {
+    if let Value::Null = value {
+        return Ok(None);
+    }
+    if !binary {
+        return Ok(Some(value_to_string(value).into_bytes()));
+    }
+    let mismatch = || {
+        WingsError::Schema(format!(
+            "Value {} can't be sent as {:?}",
+            value_to_string(value),
+            pg_type
+        ))
+    };
+    let bytes = match (pg_type, value) {
+        (PgType::Int8, Value::Integer(i)) => i.to_be_bytes().to_vec(),
+        (PgType::Int8, Value::String(s)) => s
+            .parse::<i64>()
+            .map_err(|_| mismatch())?
+            .to_be_bytes()
+            .to_vec(),
+        (PgType::Float8, Value::Integer(i)) => (*i as f64).to_be_bytes().to_vec(),
+        (PgType::Float8, Value::Float(f)) => f.to_be_bytes().to_vec(),
+        (PgType::Float8, Value::String(s)) => s
+            .parse::<f64>()
+            .map_err(|_| mismatch())?
+            .to_be_bytes()
+            .to_vec(),
+        (PgType::Text, value) => value_to_string(value).into_bytes(),
+        _ => return Err(mismatch()),
+    };
+    Ok(Some(bytes))
+}
+
+/// Decode a parameter sent by the client using the type it gave in Parse
+fn decode_param(bytes: &[u8], param_type: i32, binary: bool) -> Result<Value> {
+    let invalid = || {
+        WingsError::Protocol(format!(
+            "Invalid value for parameter of type {}",
+            param_type
+        ))
+    };
+    if !binary {
+        let text = String::from_utf8(bytes.to_vec()).map_err(|_| invalid())?;
+        return Ok(match param_type {
+            // int8, int2, int4
+            20 | 21 | 23 => Value::Integer(text.trim().parse().map_err(|_| invalid())?),
+            // float4, float8
+            700 | 701 => Value::Float(text.trim().parse().map_err(|_| invalid())?),
+            _ => Value::String(text),
+        });
+    }
+    let value = match (param_type, bytes.len()) {
+        // bool
+        (16, 1) => Value::Integer(bytes[0] as i64),
+        (20, 8) => Value::Integer(i64::from_be_bytes(bytes.try_into().unwrap())),
+        (21, 2) => Value::Integer(i16::from_be_bytes(bytes.try_into().unwrap()) as i64),
+        (23, 4) => Value::Integer(i32::from_be_bytes(bytes.try_into().unwrap()) as i64),
+        (700, 4) => Value::Float(f32::from_be_bytes(bytes.try_into().unwrap()) as f64),
+        (701, 8) => Value::Float(f64::from_be_bytes(bytes.try_into().unwrap())),
+        (0 | 25 | 1042 | 1043 | 705, _) => {
+            Value::String(String::from_utf8(bytes.to_vec()).map_err(|_| invalid())?)
+        }
+        _ => return Err(invalid()),
+    };
+    Ok(value)
+}
+
+/// Format code for a column or parameter
+/// No codes means text for all, a single code applies to all
+fn format_code(formats: &[i16], index: usize) -> i16 {
+    match formats {
+        [] => 0,
+        [format] => *format,
+        formats => formats.get(index).copied().unwrap_or(0),
+    }
+}
+
+/// Split a query string into its statements on semicolons outside of quotes
+/// and comments, leaving out statements with nothing but comments
+fn split_statements(query: &str) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut empty = true;
+    for (token, span) in query_parser::tokenize_with_spans(query) {
+        if token != Token::Symbol(';') {
+            empty = false;
+            continue;
+        }
+        if !empty {
+            statements.push(query[start..span.start].trim().to_string());
+        }
+        start = span.end;
+        empty = true;
+    }
+    if !empty {
+        statements.push(query[start..].trim().to_string());
+    }
+    statements
+}
+
+/// Highest `$n` parameter number used in a query
+fn parameter_count(query: &str) -> usize {
+    query_parser::tokenize_with_spans(query)
+        .windows(2)
+        .filter_map(|pair| match pair {
+            [(Token::Symbol('$'), dollar), (Token::Number(number), span)]
+                if dollar.end == span.start =>
+            {
+                number.parse().ok()
+            }
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Read the length-prefixed startup message, None if the client went away
+fn read_startup(reader: &mut impl Read) -> Result<Option<Vec<u8>>> {
+    let mut length = [0; 4];
+    if !read_or_eof(reader, &mut length)? {
+        return Ok(None);
+    }
+    read_payload(reader, i32::from_be_bytes(length)).map(Some)
+}
+
+/// Read a tagged message, None if the client went away
+fn read_message(reader: &mut impl Read) -> Result<Option<(u8, Vec<u8>)>> {
+    let mut header = [0; 5];
+    if !read_or_eof(reader, &mut header)? {
+        return Ok(None);
+    }
+    let length = i32::from_be_bytes(header[1..].try_into().unwrap());
+    Ok(Some((header[0], read_payload(reader, length)?)))
+}
+
+fn read_or_eof(reader: &mut impl Read, buffer: &mut [u8]) -> Result<bool> {
+    match reader.read_exact(buffer) {
+        Ok(()) => Ok(true),
+        Err(error) if error.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Read the rest of a message given its length, which includes the length itself
+fn read_payload(reader: &mut impl Read, length: i32) -> Result<Vec<u8>> {
+    let length = usize::try_from(length - 4)
+        .ok()
+        .filter(|length| *length <= MAX_MESSAGE_SIZE)
+        .ok_or_else(|| WingsError::Protocol(format!("Invalid message length {}", length)))?;
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+/// Reads fields from a message payload in order
+struct Fields<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Fields<'a> {
+    fn new(data: &'a [u8]) -> Fields<'a> {
+        Fields { data, position: 0 }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        let end = self.position + length;
+        let bytes = self
+            .data
+            .get(self.position..end)
+            .ok_or_else(|| WingsError::Protocol("Message ended early".to_string()))?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        Ok(i16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// Null terminated string
+    fn str(&mut self) -> Result<String> {
+        let rest = &self.data[self.position.min(self.data.len())..];
+        let length = rest
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or_else(|| WingsError::Protocol("String isn't terminated".to_string()))?;
+        let bytes = self.take(length + 1)?;
+        String::from_utf8(bytes[..length].to_vec())
+            .map_err(|_| WingsError::Protocol("String isn't valid UTF-8".to_string()))
+    }
+
+    /// Length-prefixed value, None for NULL
+    fn value(&mut self) -> Result<Option<&'a [u8]>> {
+        match self.i32()? {
+            -1 => Ok(None),
+            length => self.take(length.max(0) as usize).map(Some),
+        }
+    }
+
+    /// Count-prefixed list of format codes
+    fn formats(&mut self) -> Result<Vec<i16>> {
+        (0..self.i16()?).map(|_| self.i16()).collect()
+    }
+}
+
+/// Builds a message payload
+struct Payload(Vec<u8>);
+
+impl Payload {
+    fn new() -> Payload {
+        Payload(Vec::new())
+    }
+
+    fn byte(mut self, value: u8) -> Payload {
+        self.0.push(value);
+        self
+    }
+
+    fn i16(mut self, value: i16) -> Payload {
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    fn i32(mut self, value: i32) -> Payload {
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    fn bytes(mut self, value: &[u8]) -> Payload {
+        self.0.extend_from_slice(value);
+        self
+    }
+
+    /// Null terminated string
+    fn str(self, value: &str) -> Payload {
+        self.bytes(value.as_bytes()).byte(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        path::PathBuf,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use sqlite::Value;
+
+    use crate::metadata::{Format, Metadata, TableMetadata};
+    use crate::pg_server::{
+        decode_param, encode_value, handle_connection, parameter_count, split_statements, Fields,
+        Payload, PgType, PROTOCOL_VERSION,
+    };
+    use crate::{program, Wings};
+
+    #[test]
+    fn split_on_unquoted_semicolons() {
+        let statements = split_statements("select ';' as a; -- one; two\nselect 2;;");
+        assert_eq!(statements, ["select ';' as a", "-- one; two\nselect 2"]);
+        let statements = split_statements("select /* a; b */ 1; /* only a comment */;");
+        assert_eq!(statements, ["select /* a; b */ 1"]);
+    }
+
+    fn send(stream: &mut TcpStream, tag: u8, payload: Payload) {
+        stream.write_all(&[tag]).unwrap();
+        stream
+            .write_all(&(payload.0.len() as i32 + 4).to_be_bytes())
+            .unwrap();
+        stream.write_all(&payload.0).unwrap();
+    }
+
+    /// Read messages up to and including ReadyForQuery
+    fn read_until_ready(stream: &mut TcpStream) -> Vec<(u8, Vec<u8>)> {
+        let mut messages = Vec::new();
+        loop {
+            let mut header = [0; 5];
+            stream.read_exact(&mut header).unwrap();
+            let length = i32::from_be_bytes(header[1..].try_into().unwrap()) as usize;
+            let mut payload = vec![0; length - 4];
+            stream.read_exact(&mut payload).unwrap();
+            messages.push((header[0], payload));
+            if header[0] == b'Z' {
+                return messages;
+            }
+        }
+    }
+
+    fn tags(messages: &[(u8, Vec<u8>)]) -> String {
+        messages.iter().map(|(tag, _)| *tag as char).collect()
+    }
+
+    /// Text of the first column of a DataRow
+    fn first_value(payload: &[u8]) -> String {
+        let mut fields = Fields::new(payload);
+        fields.i16().unwrap();
+        String::from_utf8(fields.value().unwrap().unwrap().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn simple_and_extended_queries() {
+        let mut wings = Wings::new().unwrap();
+        wings
+            .register_table(TableMetadata {
+                metadata: Metadata {
+                    name: "test_table".to_string(),
+                    format: Format::Csv,
+                    data_path: PathBuf::from("./examples/data"),
+                    ..Default::default()
+                },
+                schema: [("id".to_string(), "int".to_string())].into(),
+                constraints: BTreeMap::new(),
+            })
+            .unwrap();
+        let wings = Arc::new(Mutex::new(wings));
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        let server_wings = wings.clone();
+        let catalog = std::env::temp_dir().join(format!("wings-pg-{}", std::process::id()));
+        let server_catalog = catalog.clone();
+        let server = thread::spawn(move || {
+            // Tables the queries don't register are looked for in a catalog of the test's own
+            program::set_test_catalog_dir(server_catalog);
+            program::init().unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            handle_connection(stream, server_wings).unwrap();
+        });
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        let startup = Payload::new()
+            .i32(PROTOCOL_VERSION)
+            .str("user")
+            .str("wings")
+            .byte(0);
+        stream
+            .write_all(&(startup.0.len() as i32 + 4).to_be_bytes())
+            .unwrap();
+        stream.write_all(&startup.0).unwrap();
+        let messages = read_until_ready(&mut stream);
+        assert_eq!(messages[0], (b'R', 0i32.to_be_bytes().to_vec()));
+        assert!(tags(&messages).ends_with("KZ"));
+
+        send(
+            &mut stream,
+            b'Q',
+            Payload::new().str("select 1 as one; select 'a' as b"),
+        );
+        let messages = read_until_ready(&mut stream);
+        assert_eq!(tags(&messages), "TDCTDCZ");
+        assert_eq!(first_value(&messages[1].1), "1");
+        assert_eq!(messages[2].1, b"SELECT 1\0");
+
+        // Describing a statement doesn't run it, so the table isn't loaded
+        let query = "select id from test_table where id > $1 order by id";
+        send(
+            &mut stream,
+            b'P',
+            Payload::new().str("ids").str(query).i16(0),
+        );
+        send(&mut stream, b'D', Payload::new().byte(b'S').str("ids"));
+        send(&mut stream, b'S', Payload::new());
+        let messages = read_until_ready(&mut stream);
+        assert_eq!(tags(&messages), "1tTZ");
+        assert_eq!(
+            messages[1].1,
+            Payload::new().i16(1).i32(PgType::Text.oid()).0
+        );
+        assert!(wings
+            .lock()
+            .unwrap()
+            .connection()
+            .execute("select * from test_table")
+            .is_err());
+
+        send(
+            &mut stream,
+            b'B',
+            Payload::new()
+                .str("")
+                .str("ids")
+                .i16(0)
+                .i16(1)
+                .i32(2)
+                .bytes(b"60")
+                .i16(0),
+        );
+        send(&mut stream, b'E', Payload::new().str("").i32(0));
+        send(&mut stream, b'S', Payload::new());
+        let messages = read_until_ready(&mut stream);
+        assert_eq!(tags(&messages), "2DCZ");
+        assert_eq!(first_value(&messages[1].1), "79");
+
+        // A portal can be fetched a row at a time
+        send(
+            &mut stream,
+            b'B',
+            Payload::new()
+                .str("")
+                .str("ids")
+                .i16(0)
+                .i16(1)
+                .i32(1)
+                .bytes(b"0")
+                .i16(0),
+        );
+        send(&mut stream, b'E', Payload::new().str("").i32(1));
+        send(&mut stream, b'E', Payload::new().str("").i32(1));
+        send(&mut stream, b'S', Payload::new());
+        let messages = read_until_ready(&mut stream);
+        assert_eq!(tags(&messages), "2DsDCZ");
+        assert_eq!(first_value(&messages[1].1), "53");
+        assert_eq!(first_value(&messages[3].1), "79");
+        assert_eq!(messages[4].1, b"SELECT 2\0");
+
+        // Only tables that don't exist are reported as undefined
+        send(
+            &mut stream,
+            b'Q',
+            Payload::new().str("select * from missing"),
+        );
+        let messages = read_until_ready(&mut stream);
+        assert_eq!(tags(&messages), "EZ");
+        let error = String::from_utf8_lossy(&messages[0].1);
+        assert!(error.contains("C42P01"), "{}", error);
+
+        // Syntax errors are reported as such, then the client can carry on
+        send(&mut stream, b'Q', Payload::new().str("selec 1"));
+        let messages = read_until_ready(&mut stream);
+        assert_eq!(tags(&messages), "EZ");
+        let error = String::from_utf8_lossy(&messages[0].1);
+        assert!(error.contains("C42601"), "{}", error);
+
+        send(&mut stream, b'X', Payload::new());
+        server.join().unwrap();
+        std::fs::remove_dir_all(&catalog).unwrap();
+    }
+
+    #[test]
+    fn count_parameters() {
+        assert_eq!(
+            parameter_count("select * from t where a = $1 and b = $12"),
+            12
+        );
+        assert_eq!(parameter_count("select '$3' from t where a = $2"), 2);
+        assert_eq!(parameter_count("select 1"), 0);
+    }
+
+    #[test]
+    fn encode_and_decode_values() {
+        assert_eq!(
+            encode_value(&Value::Integer(7), PgType::Int8, true).unwrap(),
+            Some(7i64.to_be_bytes().to_vec())
+        );
+        assert_eq!(
+            encode_value(&Value::Float(1.5), PgType::Float8, false).unwrap(),
+            Some(b"1.5".to_vec())
+        );
+        assert_eq!(
+            encode_value(&Value::Null, PgType::Text, true).unwrap(),
+            None
+        );
+        assert_eq!(
+            decode_param(&5i32.to_be_bytes(), 23, true).unwrap(),
+            Value::Integer(5)
+        );
+        assert_eq!(decode_param(b"42", 20, false).unwrap(), Value::Integer(42));
+        assert_eq!(
+            decode_param(b"abc", 0, false).unwrap(),
+            Value::String("abc".to_string())
+        );
+    }
+}
@@ -1,25 +1,145 @@
-use std::fs;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
 
 use dirs::home_dir;
 
-pub fn init() {
-  let homedir = home_dir().unwrap_or_else(|| {
-      panic!("Cannot find home directory, create home directory to continue.")
-  });
-  let mut wings_dir = homedir.clone();
-  wings_dir.push(".wings");
-  wings_dir.push("tables");
-  if !wings_dir.exists() {
-      fs::create_dir_all(wings_dir)
-          .unwrap_or_else(|err| panic!("Error creating config, {}", err));
-  }
-}
-
-pub fn does_program_directory_exist() -> bool {
-  let homedir = home_dir().unwrap_or_else(|| {
-    panic!("Cannot find home directory, create home directory to continue.")
-  });
-  let mut wings_dir = homedir.clone();
-  wings_dir.push(".wings");
-  wings_dir.exists()
-}
\ No newline at end of file
+use crate::error::{Result, WingsError};
+
+/// Name of the wings program directory, in the home directory or a project
+pub const PROGRAM_DIR_NAME: &str = ".wings";
+
+/// Environment variable that sets the program directory
+pub const WINGS_HOME: &str = "WINGS_HOME";
+
+/// Program directory set with `--catalog`, ahead of everything else
+static CATALOG_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// Use the given directory as the program directory for this process
+pub fn set_catalog_dir(dir: PathBuf) {
+    *CATALOG_DIR.write().unwrap() = Some(dir);
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Program directory for the test running on this thread, so tests can
+    /// have a catalog of their own while others run
+    static TEST_CATALOG_DIR: std::cell::RefCell<Option<PathBuf>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+/// Use the given directory as the program directory for this test
+#[cfg(test)]
+pub fn set_test_catalog_dir(dir: PathBuf) {
+    TEST_CATALOG_DIR.with(|catalog_dir| *catalog_dir.borrow_mut() = Some(dir));
+}
+
+/// Get the wings program directory
+///
+/// The first of these is used:
+/// the directory set with [`set_catalog_dir`], `$WINGS_HOME`,
+/// a `.wings` directory in the current directory or one of its parents,
+/// and finally `~/.wings`.
+pub fn program_dir() -> Result<PathBuf> {
+    #[cfg(test)]
+    if let Some(dir) = TEST_CATALOG_DIR.with(|catalog_dir| catalog_dir.borrow().clone()) {
+        return Ok(dir);
+    }
+    if let Some(dir) = CATALOG_DIR.read().unwrap().clone() {
+        return Ok(dir);
+    }
+    if let Some(dir) = env::var_os(WINGS_HOME).filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+    if let Some(dir) = find_project_dir(&env::current_dir()?) {
+        return Ok(dir);
+    }
+    home_program_dir()
+}
+
+/// Get ~/.wings
+pub fn home_program_dir() -> Result<PathBuf> {
+    let mut wings_dir = home_dir().ok_or_else(|| {
+        WingsError::Metadata(
+            "Cannot find home directory, create home directory to continue.".to_string(),
+        )
+    })?;
+    wings_dir.push(PROGRAM_DIR_NAME);
+    Ok(wings_dir)
+}
+
+/// Find a `.wings` directory in start or the closest of its parents
+pub fn find_project_dir(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(PROGRAM_DIR_NAME))
+        .find(|dir| dir.is_dir())
+}
+
+/// Get the directory table definitions are stored in
+pub fn tables_dir() -> Result<PathBuf> {
+    let mut tables_dir = program_dir()?;
+    tables_dir.push("tables");
+    Ok(tables_dir)
+}
+
+/// Get the directory view definitions are stored in, next to the tables
+pub fn views_dir() -> Result<PathBuf> {
+    let mut views_dir = program_dir()?;
+    views_dir.push("views");
+    Ok(views_dir)
+}
+
+pub fn init() -> Result<()> {
+    let wings_dir = tables_dir()?;
+    if !wings_dir.exists() {
+        fs::create_dir_all(wings_dir)?;
+    }
+    Ok(())
+}
+
+/// Create a project catalog in `.wings` under the current directory and use it
+pub fn init_local() -> Result<()> {
+    set_catalog_dir(env::current_dir()?.join(PROGRAM_DIR_NAME));
+    init()
+}
+
+pub fn does_program_directory_exist() -> Result<bool> {
+    Ok(program_dir()?.exists())
+}
+
+/// Fail unless `wings init` has been run
+pub fn ensure_initialized() -> Result<()> {
+    if does_program_directory_exist()? {
+        Ok(())
+    } else {
+        Err(WingsError::Metadata(
+            "Wings not initialized, run `wings init` first.".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use crate::program::find_project_dir;
+
+    #[test]
+    fn find_project_dir_in_parents() {
+        let root = env::temp_dir().join(format!("wings-project-{}", std::process::id()));
+        let nested = root.join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+        fs::create_dir_all(root.join(".wings")).unwrap();
+        fs::create_dir_all(root.join("a").join(".wings")).unwrap();
+
+        assert_eq!(
+            find_project_dir(&nested),
+            Some(root.join("a").join(".wings"))
+        );
+        assert_eq!(find_project_dir(&root), Some(root.join(".wings")));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,152 @@
+use std::{
+    collections::HashMap,
+    io::{self, IsTerminal, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+use crate::formats::Chunk;
+
+/// Minimum time between progress updates
+const RENDER_INTERVAL_MS: u64 = 100;
+
+/// Progress of loading a table, written to stderr as reader threads go
+///
+/// Reporting is off unless stderr is a terminal, so piped or redirected
+/// output never has progress lines mixed in.
+pub struct Progress {
+    enabled: bool,
+    table: String,
+    started: Instant,
+    files_total: usize,
+    files_done: AtomicUsize,
+    /// Chunks left to read for each file
+    chunks_left: Mutex<HashMap<PathBuf, usize>>,
+    rows: AtomicU64,
+    bytes: AtomicU64,
+    last_render_ms: AtomicU64,
+}
+
+impl Progress {
+    pub fn new(table: &str, chunks: &[Chunk], enabled: bool) -> Progress {
+        let mut chunks_left: HashMap<PathBuf, usize> = HashMap::new();
+        for chunk in chunks {
+            *chunks_left.entry(chunk.path.clone()).or_insert(0) += 1;
+        }
+        Progress {
+            enabled: enabled && io::stderr().is_terminal(),
+            table: table.to_string(),
+            started: Instant::now(),
+            files_total: chunks_left.len(),
+            files_done: AtomicUsize::new(0),
+            chunks_left: Mutex::new(chunks_left),
+            rows: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            last_render_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Progress that never reports
+    pub fn disabled() -> Progress {
+        Progress::new("", &[], false)
+    }
+
+    /// Add rows read from a batch
+    pub fn add_rows(&self, rows: u64) {
+        if !self.enabled {
+            return;
+        }
+        self.rows.fetch_add(rows, Ordering::Relaxed);
+        self.render(false);
+    }
+
+    /// Mark a chunk as read
+    pub fn chunk_done(&self, chunk: &Chunk, bytes: u64) {
+        if !self.enabled {
+            return;
+        }
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        let mut chunks_left = self.chunks_left.lock().unwrap();
+        if let Some(left) = chunks_left.get_mut(&chunk.path) {
+            *left -= 1;
+            if *left == 0 {
+                self.files_done.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        drop(chunks_left);
+        self.render(false);
+    }
+
+    /// Write the final progress line
+    pub fn finish(&self) {
+        if !self.enabled {
+            return;
+        }
+        self.render(true);
+        eprintln!();
+    }
+
+    fn render(&self, force: bool) {
+        let elapsed_ms = self.started.elapsed().as_millis() as u64;
+        let last = self.last_render_ms.load(Ordering::Relaxed);
+        if !force {
+            // Only one thread renders each interval
+            let due = elapsed_ms >= last + RENDER_INTERVAL_MS;
+            if !due
+                || self
+                    .last_render_ms
+                    .compare_exchange(last, elapsed_ms, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_err()
+            {
+                return;
+            }
+        }
+
+        let rows = self.rows.load(Ordering::Relaxed);
+        let rows_per_second = rows * 1000 / elapsed_ms.max(1);
+        let mut stderr = io::stderr().lock();
+        let _ = write!(
+            stderr,
+            "\r\x1b[2KLoading {}: {}/{} files, {} rows ({} rows/s), {}",
+            self.table,
+            self.files_done.load(Ordering::Relaxed),
+            self.files_total,
+            rows,
+            rows_per_second,
+            format_bytes(self.bytes.load(Ordering::Relaxed))
+        );
+        let _ = stderr.flush();
+    }
+}
+
+/// Format a byte count with a binary unit
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[unit])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::progress::format_bytes;
+
+    #[test]
+    fn byte_units() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+}
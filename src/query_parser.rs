@@ -1,21 +1,561 @@
+use std::{collections::HashMap, ops::Range};
 
-pub fn get_tables_from_query(query: &String) -> Vec<String> {
-  // Parse query, get tables, load config, load table, run query
-  // Does not work for multiple tables yet
-  let mut clauses: Vec<&str> = query.split("from").collect();
-  clauses.remove(0); // Not performant
-  let mut tables: Vec<String> = vec![];
-  for clause in clauses {
-      let words: Vec<&str> = clause.split(" ").collect();
-      let table = words[1].to_string();
-      tables.push(table)
-  }
-  return tables;
+/// Piece of a query as split up by [`tokenize`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    /// Keyword or unquoted identifier
+    Word(String),
+    /// Identifier quoted with double quotes, backticks or brackets
+    Identifier(String),
+    /// Single quoted string literal with quotes removed
+    String(String),
+    Number(String),
+    Symbol(char),
+}
+
+impl Token {
+    /// Whether this is the given keyword, ignoring case
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, Token::Word(word) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    /// Identifier or word this token names, if any
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Token::Word(name) | Token::Identifier(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+/// Keywords that can follow a table name, so they are never an alias
+const CLAUSE_KEYWORDS: [&str; 20] = [
+    "where",
+    "join",
+    "inner",
+    "left",
+    "right",
+    "full",
+    "cross",
+    "natural",
+    "on",
+    "using",
+    "group",
+    "order",
+    "having",
+    "limit",
+    "offset",
+    "union",
+    "intersect",
+    "except",
+    "window",
+    "outer",
+];
+
+/// Functions that aggregate rows, so every row has to be loaded
+const AGGREGATE_FUNCTIONS: [&str; 7] =
+    ["count", "sum", "avg", "min", "max", "total", "group_concat"];
+
+/// Keywords that mean a query needs more than the first rows of a table
+const FULL_SCAN_KEYWORDS: [&str; 11] = [
+    "where",
+    "join",
+    "order",
+    "group",
+    "having",
+    "distinct",
+    "union",
+    "intersect",
+    "except",
+    "over",
+    "window",
+];
+
+/// Split a query into tokens, dropping whitespace and comments
+pub fn tokenize(query: &str) -> Vec<Token> {
+    tokenize_with_spans(query)
+        .into_iter()
+        .map(|(token, _)| token)
+        .collect()
+}
+
+/// Split a query into tokens along with the byte range each was read from
+pub fn tokenize_with_spans(query: &str) -> Vec<(Token, Range<usize>)> {
+    let chars: Vec<char> = query.chars().collect();
+    // Byte offset of each char, and of the end of the query
+    let offsets: Vec<usize> = query
+        .char_indices()
+        .map(|(offset, _)| offset)
+        .chain([query.len()])
+        .collect();
+    let mut tokens: Vec<(Token, Range<usize>)> = Vec::new();
+    let mut index = 0;
+    while index < chars.len() {
+        let c = chars[index];
+        let start = index;
+        let token = if c.is_whitespace() {
+            index += 1;
+            None
+        } else if c == '-' && chars.get(index + 1) == Some(&'-') {
+            while index < chars.len() && chars[index] != '\n' {
+                index += 1;
+            }
+            None
+        } else if c == '/' && chars.get(index + 1) == Some(&'*') {
+            index += 2;
+            while index < chars.len()
+                && !(chars[index] == '*' && chars.get(index + 1) == Some(&'/'))
+            {
+                index += 1;
+            }
+            index += 2;
+            None
+        } else if c == '\'' || c == '"' || c == '`' || c == '[' {
+            let close = if c == '[' { ']' } else { c };
+            let mut value = String::new();
+            index += 1;
+            while index < chars.len() {
+                if chars[index] == close {
+                    // Doubled quotes are an escaped quote
+                    if close != ']' && chars.get(index + 1) == Some(&close) {
+                        value.push(close);
+                        index += 2;
+                        continue;
+                    }
+                    break;
+                }
+                value.push(chars[index]);
+                index += 1;
+            }
+            index += 1;
+            Some(if c == '\'' {
+                Token::String(value)
+            } else {
+                Token::Identifier(value)
+            })
+        } else if c.is_ascii_digit() {
+            while index < chars.len() && (chars[index].is_ascii_digit() || chars[index] == '.') {
+                index += 1;
+            }
+            Some(Token::Number(chars[start..index].iter().collect()))
+        } else if c.is_alphanumeric() || c == '_' {
+            while index < chars.len()
+                && (chars[index].is_alphanumeric() || chars[index] == '_' || chars[index] == '$')
+            {
+                index += 1;
+            }
+            Some(Token::Word(chars[start..index].iter().collect()))
+        } else {
+            index += 1;
+            Some(Token::Symbol(c))
+        };
+        if let Some(token) = token {
+            let end = index.min(chars.len());
+            tokens.push((token, offsets[start]..offsets[end]));
+        }
+    }
+    tokens
+}
+
+pub fn get_tables_from_query(query: &str) -> Vec<String> {
+    // Parse query, get tables, load config, load table, run query
+    get_tables_from_tokens(&tokenize(query))
+}
+
+/// Get the tables named after FROM and JOIN, in order and without duplicates
+/// Names defined by a WITH clause are left out since they aren't tables
+pub fn get_tables_from_tokens(tokens: &[Token]) -> Vec<String> {
+    let common_tables = get_common_table_names(tokens);
+    let mut tables: Vec<String> = vec![];
+    for (reference, _) in get_table_references(tokens) {
+        if let TableReference::Table(table) = reference {
+            if !common_tables.contains(&table) && !tables.contains(&table) {
+                tables.push(table);
+            }
+        }
+    }
+    tables
+}
+
+/// What a query reads from after FROM or JOIN
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableReference {
+    /// Table referenced by name
+    Table(String),
+    /// Files referenced by path, as `'data/*.csv'` or `read_csv('data/')`
+    /// The format is only known when it's given by the function name
+    File {
+        path: String,
+        format: Option<String>,
+    },
+}
+
+/// Get every table and file a query reads from, with the range of tokens
+/// each was written in
+pub fn get_table_references(tokens: &[Token]) -> Vec<(TableReference, Range<usize>)> {
+    let mut references = Vec::new();
+    let mut index = 0;
+    while index < tokens.len() {
+        let token = &tokens[index];
+        index += 1;
+        if !(token.is_keyword("from") || token.is_keyword("join")) {
+            continue;
+        }
+        // FROM can list several tables separated by commas
+        loop {
+            let start = index;
+            let reference = match tokens.get(index) {
+                Some(Token::String(path)) => {
+                    index += 1;
+                    Some(TableReference::File {
+                        path: path.clone(),
+                        format: None,
+                    })
+                }
+                Some(Token::Word(function))
+                    if tokens.get(index + 1) == Some(&Token::Symbol('(')) =>
+                {
+                    let format = function
+                        .to_lowercase()
+                        .strip_prefix("read_")
+                        .map(str::to_string);
+                    match (format, tokens.get(index + 2), tokens.get(index + 3)) {
+                        (Some(format), Some(Token::String(path)), Some(Token::Symbol(')'))) => {
+                            index += 4;
+                            Some(TableReference::File {
+                                path: path.clone(),
+                                format: Some(format),
+                            })
+                        }
+                        // Other table valued functions aren't tables to load
+                        _ => {
+                            index = skip_parentheses(tokens, index + 1);
+                            None
+                        }
+                    }
+                }
+                _ => match parse_table_name(tokens, index) {
+                    Some((table, next)) => {
+                        index = next;
+                        Some(TableReference::Table(table))
+                    }
+                    None => break,
+                },
+            };
+            if let Some(reference) = reference {
+                references.push((reference, start..index));
+            }
+            index = skip_alias(tokens, index);
+            if token.is_keyword("from") && tokens.get(index) == Some(&Token::Symbol(',')) {
+                index += 1;
+            } else {
+                break;
+            }
+        }
+    }
+    references
+}
+
+/// Files a query references directly, with the byte range of each reference
+/// in the query
+pub fn get_file_references(query: &str) -> Vec<(TableReference, Range<usize>)> {
+    let spans = tokenize_with_spans(query);
+    let tokens: Vec<Token> = spans.iter().map(|(token, _)| token.clone()).collect();
+    get_table_references(&tokens)
+        .into_iter()
+        .filter(|(reference, _)| matches!(reference, TableReference::File { .. }))
+        .map(|(reference, range)| {
+            (
+                reference,
+                spans[range.start].1.start..spans[range.end - 1].1.end,
+            )
+        })
+        .collect()
+}
+
+/// Get the index after the parentheses that open at index
+fn skip_parentheses(tokens: &[Token], index: usize) -> usize {
+    let mut depth = 0;
+    for (offset, token) in tokens[index..].iter().enumerate() {
+        match token {
+            Token::Symbol('(') => depth += 1,
+            Token::Symbol(')') => {
+                depth -= 1;
+                if depth == 0 {
+                    return index + offset + 1;
+                }
+            }
+            _ => (),
+        }
+    }
+    tokens.len()
+}
+
+/// Parse a possibly qualified name like `schema.table` starting at index
+/// Returns the name and the index after it
+pub fn parse_table_name(tokens: &[Token], index: usize) -> Option<(String, usize)> {
+    let mut name = tokens.get(index)?.name()?.to_string();
+    let mut index = index + 1;
+    while tokens.get(index) == Some(&Token::Symbol('.')) {
+        match tokens.get(index + 1).and_then(Token::name) {
+            Some(part) => {
+                name = name + "." + part;
+                index += 2;
+            }
+            None => break,
+        }
+    }
+    Some((name, index))
+}
+
+/// Skip a table alias, with or without AS, starting at index
+fn skip_alias(tokens: &[Token], index: usize) -> usize {
+    match tokens.get(index) {
+        Some(token) if token.is_keyword("as") => index + 2,
+        Some(Token::Identifier(_)) => index + 1,
+        Some(token @ Token::Word(_))
+            if !CLAUSE_KEYWORDS
+                .iter()
+                .any(|keyword| token.is_keyword(keyword)) =>
+        {
+            index + 1
+        }
+        _ => index,
+    }
+}
+
+/// Get the alias a table is given, with or without AS, starting at index
+fn get_alias(tokens: &[Token], index: usize) -> Option<String> {
+    match tokens.get(index)? {
+        token if token.is_keyword("as") => tokens.get(index + 1)?.name().map(str::to_string),
+        Token::Identifier(alias) => Some(alias.clone()),
+        token @ Token::Word(alias)
+            if !CLAUSE_KEYWORDS
+                .iter()
+                .any(|keyword| token.is_keyword(keyword)) =>
+        {
+            Some(alias.clone())
+        }
+        _ => None,
+    }
+}
+
+/// Tables a query reads from by the names it refers to them with, which
+/// are their aliases or, without one, their own names
+pub fn get_table_aliases(tokens: &[Token]) -> HashMap<String, String> {
+    let mut aliases: HashMap<String, String> = HashMap::new();
+    for (reference, range) in get_table_references(tokens) {
+        if let TableReference::Table(table) = reference {
+            // A table with an alias can only be referred to by it
+            if let Some(alias) = get_alias(tokens, range.end) {
+                aliases.insert(alias, table);
+                continue;
+            }
+            // Tables in a namespace can be referred to without it
+            if let Some((_, name)) = table.split_once('.') {
+                aliases
+                    .entry(name.to_string())
+                    .or_insert_with(|| table.clone());
+            }
+            aliases.insert(table.clone(), table);
+        }
+    }
+    aliases
+}
+
+/// Columns a query compares, like join keys and filters, each with the
+/// table it's qualified with
+/// Unqualified columns have no table, and columns qualified with a name
+/// that isn't a table, like a subquery's alias, are left out
+pub fn get_compared_columns(query: &str) -> Vec<(Option<String>, String)> {
+    let tokens = tokenize(query);
+    let aliases = get_table_aliases(&tokens);
+    let is_comparison = |token: &Token| matches!(token, Token::Symbol('=' | '<' | '>' | '!'));
+    let mut columns: Vec<(Option<String>, String)> = Vec::new();
+    let mut index = 0;
+    while index < tokens.len() {
+        if !is_comparison(&tokens[index]) {
+            index += 1;
+            continue;
+        }
+        let start = index;
+        while index < tokens.len() && is_comparison(&tokens[index]) {
+            index += 1;
+        }
+        let operands = [column_before(&tokens, start), column_at(&tokens, index)];
+        for (qualifier, column) in operands.into_iter().flatten() {
+            let table = match qualifier {
+                Some(qualifier) => match aliases.get(&qualifier) {
+                    Some(table) => Some(table.clone()),
+                    None => continue,
+                },
+                None => None,
+            };
+            if !columns.contains(&(table.clone(), column.clone())) {
+                columns.push((table, column));
+            }
+        }
+    }
+    columns
+}
+
+/// Column, and what it's qualified with, written just before end
+fn column_before(tokens: &[Token], end: usize) -> Option<(Option<String>, String)> {
+    let column = tokens.get(end.checked_sub(1)?)?.name()?.to_string();
+    let qualifier = match end.checked_sub(3) {
+        Some(index) if tokens[index + 1] == Token::Symbol('.') => {
+            Some(tokens[index].name()?.to_string())
+        }
+        _ => None,
+    };
+    Some((qualifier, column))
+}
+
+/// Column, and what it's qualified with, written starting at index
+/// Function calls aren't columns
+fn column_at(tokens: &[Token], index: usize) -> Option<(Option<String>, String)> {
+    let name = tokens.get(index)?.name()?.to_string();
+    match tokens.get(index + 1) {
+        Some(Token::Symbol('.')) => {
+            let column = tokens.get(index + 2)?.name()?.to_string();
+            (tokens.get(index + 3) != Some(&Token::Symbol('('))).then_some((Some(name), column))
+        }
+        Some(Token::Symbol('(')) => None,
+        _ => Some((None, name)),
+    }
+}
+
+/// Names defined with `WITH name AS (...)`
+fn get_common_table_names(tokens: &[Token]) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for (index, token) in tokens.iter().enumerate() {
+        if token.is_keyword("as") && tokens.get(index + 1) == Some(&Token::Symbol('(')) {
+            if let Some(name) = index.checked_sub(1).and_then(|i| tokens[i].name()) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names
+}
+
+/// Get the number of rows to load for a plain scan of one table with a LIMIT
+/// Returns None when the query needs every row, such as with ORDER BY,
+/// aggregates, joins or filters
+pub fn get_scan_limit(query: &str) -> Option<u64> {
+    let tokens = tokenize(query);
+    let selects = tokens
+        .iter()
+        .filter(|token| token.is_keyword("select"))
+        .count();
+    if selects != 1 || get_tables_from_tokens(&tokens).len() != 1 {
+        return None;
+    }
+    for (index, token) in tokens.iter().enumerate() {
+        if FULL_SCAN_KEYWORDS
+            .iter()
+            .any(|keyword| token.is_keyword(keyword))
+        {
+            return None;
+        }
+        let is_aggregate = AGGREGATE_FUNCTIONS
+            .iter()
+            .any(|function| token.is_keyword(function));
+        if is_aggregate && tokens.get(index + 1) == Some(&Token::Symbol('(')) {
+            return None;
+        }
+    }
+
+    let limit_index = tokens.iter().position(|token| token.is_keyword("limit"))?;
+    let limit = parse_number(tokens.get(limit_index + 1)?)?;
+    match tokens.get(limit_index + 2) {
+        // LIMIT count OFFSET skip
+        Some(token) if token.is_keyword("offset") => {
+            Some(limit + parse_number(tokens.get(limit_index + 3)?)?)
+        }
+        // LIMIT skip, count
+        Some(Token::Symbol(',')) => Some(limit + parse_number(tokens.get(limit_index + 3)?)?),
+        Some(Token::Symbol(';')) | None => Some(limit),
+        Some(_) => None,
+    }
+}
+
+/// Get the table and result column name for an unfiltered `select count(*) from table`,
+/// which can be answered without loading the table
+pub fn get_count_query(query: &str) -> Option<(String, String)> {
+    let spanned = tokenize_with_spans(query);
+    let tokens: Vec<Token> = spanned.iter().map(|(token, _)| token.clone()).collect();
+    let is_count = tokens.first()?.is_keyword("select")
+        && tokens.get(1)?.is_keyword("count")
+        && tokens.get(2)? == &Token::Symbol('(')
+        && matches!(tokens.get(3)?, Token::Symbol('*') | Token::Number(_))
+        && tokens.get(4)? == &Token::Symbol(')');
+    if !is_count {
+        return None;
+    }
+
+    let mut index = 5;
+    let mut alias: Option<String> = None;
+    if tokens.get(index)?.is_keyword("as") {
+        alias = Some(tokens.get(index + 1)?.name()?.to_string());
+        index += 2;
+    } else if !tokens.get(index)?.is_keyword("from") {
+        alias = Some(tokens.get(index)?.name()?.to_string());
+        index += 1;
+    }
+    if !tokens.get(index)?.is_keyword("from") {
+        return None;
+    }
+    let (table, index) = parse_table_name(&tokens, index + 1)?;
+    match tokens.get(index) {
+        None | Some(Token::Symbol(';')) if index + 1 >= tokens.len() => (),
+        _ => return None,
+    }
+
+    // Without an alias the column is named after the expression as written
+    let column = match alias {
+        Some(alias) => alias,
+        None => query[spanned[1].1.start..spanned[4].1.end].to_string(),
+    };
+    Some((table, column))
+}
+
+fn parse_number(token: &Token) -> Option<u64> {
+    match token {
+        Token::Number(number) => number.parse().ok(),
+        _ => None,
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::query_parser::get_tables_from_query;
+    use crate::query_parser::{
+        get_compared_columns, get_count_query, get_file_references, get_scan_limit,
+        get_tables_from_query, tokenize, TableReference, Token,
+    };
+
+    #[test]
+    fn file_references() {
+        let query =
+            "select * from events e, read_jsonl('logs/') l join 'data/*.csv' d on e.id = d.id";
+        assert_eq!(get_tables_from_query(query), vec!["events"]);
+        let references = get_file_references(query);
+        assert_eq!(
+            references[0].0,
+            TableReference::File {
+                path: "logs/".to_string(),
+                format: Some("jsonl".to_string())
+            }
+        );
+        assert_eq!(&query[references[0].1.clone()], "read_jsonl('logs/')");
+        assert_eq!(
+            references[1].0,
+            TableReference::File {
+                path: "data/*.csv".to_string(),
+                format: None
+            }
+        );
+        assert_eq!(&query[references[1].1.clone()], "'data/*.csv'");
+    }
 
     #[test]
     fn got_table_from_query() {
@@ -23,4 +563,94 @@ mod tests {
         let result = vec![String::from("test_table")];
         assert_eq!(get_tables_from_query(&query), result);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn got_tables_from_joins() {
+        let query =
+            "SELECT a.id FROM first a, third JOIN second AS b ON a.id = b.id where from_date > 1";
+        assert_eq!(
+            get_tables_from_query(query),
+            vec!["first", "third", "second"]
+        );
+    }
+
+    #[test]
+    fn compared_columns() {
+        let query = "SELECT a.id FROM first a JOIN second AS b ON a.id = b.first_id \
+            WHERE lower(b.name) = 'x' AND created >= 5 AND s.total <> 0";
+        assert_eq!(
+            get_compared_columns(query),
+            vec![
+                (Some("first".to_string()), "id".to_string()),
+                (Some("second".to_string()), "first_id".to_string()),
+                (None, "created".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn skip_common_tables() {
+        let query = "with recent as (select * from events limit 5) select * from recent";
+        assert_eq!(get_tables_from_query(query), vec!["events"]);
+    }
+
+    #[test]
+    fn tokenize_quotes_and_comments() {
+        let tokens = tokenize("select 'it''s', \"my col\" -- comment\nfrom t /* x */");
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Word("select".to_string()),
+                Token::String("it's".to_string()),
+                Token::Symbol(','),
+                Token::Identifier("my col".to_string()),
+                Token::Word("from".to_string()),
+                Token::Word("t".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn scan_limit() {
+        assert_eq!(get_scan_limit("select * from big limit 10"), Some(10));
+        assert_eq!(
+            get_scan_limit("select id from big LIMIT 10 OFFSET 5;"),
+            Some(15)
+        );
+        assert_eq!(get_scan_limit("select id from big limit 5, 10"), Some(15));
+        assert_eq!(get_scan_limit("select * from big"), None);
+        assert_eq!(
+            get_scan_limit("select * from big order by id limit 10"),
+            None
+        );
+        assert_eq!(get_scan_limit("select count(*) from big limit 10"), None);
+        assert_eq!(
+            get_scan_limit("select * from big where id = 1 limit 10"),
+            None
+        );
+        assert_eq!(get_scan_limit("select * from a, b limit 10"), None);
+        assert_eq!(get_scan_limit("select distinct id from big limit 10"), None);
+    }
+
+    #[test]
+    fn count_query() {
+        let count = |query: &str| get_count_query(query);
+        let expected = |table: &str, column: &str| Some((table.to_string(), column.to_string()));
+        assert_eq!(
+            count("select count(*) from big"),
+            expected("big", "count(*)")
+        );
+        assert_eq!(
+            count("SELECT COUNT(*) FROM big;"),
+            expected("big", "COUNT(*)")
+        );
+        assert_eq!(count("select count(1) as n from big"), expected("big", "n"));
+        assert_eq!(
+            count("/* counts */ select Count( * ) from big"),
+            expected("big", "Count( * )")
+        );
+        assert_eq!(count("select count(*) from big where id = 1"), None);
+        assert_eq!(count("select count(id) from big"), None);
+        assert_eq!(count("select count(*) from a, b"), None);
+    }
+}
@@ -1,22 +1,113 @@
-use std::{collections::BTreeMap, path::PathBuf};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, SyncSender},
+        Mutex,
+    },
+    thread,
+};
 
 use rayon::prelude::*;
 
-use crate::utils::read_path;
-
-pub fn load(files: Vec<PathBuf>, format: String) -> Vec<Vec<BTreeMap<String, String>>> {
-    let rayon_rows: Vec<Vec<BTreeMap<String, String>>> = files
-        .par_iter()
-        .map(|entry| {
-            let raw_rows = read_path(entry.as_path(), &format);
-            match raw_rows {
-                Ok(ok_rows) => {
-                    // Add projection function here
-                    return ok_rows;
+use crate::data_loader::{number_rows, read_chunk, Batch};
+use crate::error::Result;
+use crate::formats::{Chunk, FormatReader};
+use crate::progress::Progress;
+use crate::stats::FileStatsCollector;
+
+/// Number of batches each chunk can hold before its reader blocks when
+/// rows are sent in order
+const CHUNK_CHANNEL_CAPACITY: usize = 4;
+
+pub fn load(
+    chunks: Vec<Chunk>,
+    reader: &dyn FormatReader,
+    sender: SyncSender<Result<Batch>>,
+    row_numbers: bool,
+    file_stats: &FileStatsCollector,
+    progress: &Progress,
+) {
+    if row_numbers {
+        load_in_order(chunks, reader, sender, file_stats, progress);
+        return;
+    }
+
+    // Set once the writer hangs up, so chunks not started yet are left unread
+    let closed = AtomicBool::new(false);
+    chunks.par_iter().for_each_with(sender, |sender, chunk| {
+        if closed.load(Ordering::Relaxed) {
+            return;
+        }
+        // Add projection function here
+        let result = read_chunk(chunk, reader, file_stats, progress, |batch| {
+            let sent = sender.send(Ok(batch)).is_ok();
+            if !sent {
+                closed.store(true, Ordering::Relaxed);
+            }
+            sent
+        });
+        if let Err(error) = result {
+            let _ = sender.send(Err(error));
+        }
+    });
+}
+
+/// Read chunks in parallel but send their rows in file order so each row
+/// can be numbered
+///
+/// Chunks are claimed in order and each has its own bounded channel, so the
+/// chunk being sent is always being read and memory stays bounded.
+fn load_in_order(
+    chunks: Vec<Chunk>,
+    reader: &dyn FormatReader,
+    sender: SyncSender<Result<Batch>>,
+    file_stats: &FileStatsCollector,
+    progress: &Progress,
+) {
+    let mut chunk_senders: Vec<Mutex<Option<SyncSender<Result<Batch>>>>> = Vec::new();
+    let mut chunk_receivers = Vec::new();
+    for _ in &chunks {
+        let (chunk_sender, chunk_receiver) = mpsc::sync_channel(CHUNK_CHANNEL_CAPACITY);
+        chunk_senders.push(Mutex::new(Some(chunk_sender)));
+        chunk_receivers.push(chunk_receiver);
+    }
+
+    let next_chunk = AtomicUsize::new(0);
+    let closed = AtomicBool::new(false);
+    let workers = rayon::current_num_threads().min(chunks.len());
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let index = next_chunk.fetch_add(1, Ordering::SeqCst);
+                if index >= chunks.len() || closed.load(Ordering::Relaxed) {
+                    break;
+                }
+                // Taking the sender drops it once the chunk is read, ending its channel
+                let Some(chunk_sender) = chunk_senders[index].lock().unwrap().take() else {
+                    continue;
+                };
+                let result = read_chunk(&chunks[index], reader, file_stats, progress, |batch| {
+                    chunk_sender.send(Ok(batch)).is_ok()
+                });
+                if let Err(error) = result {
+                    let _ = chunk_sender.send(Err(error));
+                }
+            });
+        }
+
+        let mut row_number: u64 = 0;
+        // Stopping early drops the remaining receivers, which stops the readers
+        'chunks: for chunk_receiver in chunk_receivers {
+            for batch in chunk_receiver {
+                let batch = batch.map(|mut batch| {
+                    number_rows(&mut batch, &mut row_number);
+                    batch
+                });
+                if sender.send(batch).is_err() {
+                    closed.store(true, Ordering::Relaxed);
+                    break 'chunks;
                 }
-                Err(_) => panic!("Cannot read data rows"),
             }
-        })
-        .collect();
-    return rayon_rows;
+        }
+    });
 }
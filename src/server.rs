@@ -0,0 +1,557 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use serde_json::{json, Value as JsonValue};
+use sqlite::Value;
+
+use crate::error::{Result, WingsError};
+use crate::metadata;
+use crate::session::{QueryResult, Wings};
+
+/// Largest request body accepted, in bytes
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+/// How long a client has to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a client has to take the response
+const WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Host names the server answers to, since it only listens on localhost
+const LOCAL_HOSTS: [&str; 3] = ["localhost", "127.0.0.1", "[::1]"];
+
+/// HTTP server answering queries against the catalog
+///
+/// Each connection gets its own thread, but they share one session so tables
+/// loaded by one request stay warm for the next. Requests from web pages on
+/// other origins are refused, since any page open in a browser can reach
+/// localhost.
+pub struct Server {
+    wings: Arc<Mutex<Wings>>,
+}
+
+/// Parsed HTTP request
+#[derive(Debug, PartialEq)]
+pub struct Request {
+    pub method: String,
+    /// Still percent-encoded, so an encoded `/` stays in its segment
+    pub path: String,
+    /// Decoded query parameters
+    pub params: HashMap<String, String>,
+    /// Header names are lowercase
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+/// HTTP response sent back to the client
+#[derive(Debug, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: JsonValue) -> Response {
+        Response {
+            status,
+            content_type: "application/json",
+            body: body.to_string(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Response {
+        Response::json(status, json!({ "error": message }))
+    }
+
+    /// Not found for a table or view that doesn't exist, a server error for
+    /// anything else that went wrong looking it up
+    fn lookup_error(error: &WingsError) -> Response {
+        match error {
+            WingsError::NotFound(_) => Response::error(404, &error.to_string()),
+            _ => Response::error(500, &error.to_string()),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Internal Server Error",
+        }
+    }
+
+    /// Write the response with its status line and headers
+    pub fn write_to(&self, writer: &mut impl Write) -> Result<()> {
+        write!(
+            writer,
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            self.reason(),
+            self.content_type,
+            self.body.len(),
+            self.body
+        )?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl Server {
+    pub fn new(wings: Wings) -> Server {
+        Server {
+            wings: Arc::new(Mutex::new(wings)),
+        }
+    }
+
+    /// Accept connections on localhost until the process is stopped
+    pub fn serve(&self, port: u16) -> Result<()> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        log::info!("Listening on http://{}", listener.local_addr()?);
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let server = Server {
+                        wings: self.wings.clone(),
+                    };
+                    thread::spawn(move || {
+                        if let Err(error) = server.handle_connection(stream) {
+                            log::warn!("Failed to handle request: {}", error);
+                        }
+                    });
+                }
+                Err(error) => log::warn!("Failed to accept connection: {}", error),
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_connection(&self, stream: TcpStream) -> Result<()> {
+        // A client that stops sending or reading is dropped
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        let mut reader = BufReader::new(&stream);
+        let response = match read_request(&mut reader) {
+            Ok(request) => self.respond(&request),
+            Err(WingsError::Protocol(message)) => Response::error(400, &message),
+            Err(error) => return Err(error),
+        };
+        response.write_to(&mut &stream)
+    }
+
+    /// Route a request and build its response
+    pub fn respond(&self, request: &Request) -> Response {
+        if let Some(message) = check_origin(request) {
+            return Response::error(403, &message);
+        }
+        let segments: Vec<String> = match request
+            .path
+            .trim_matches('/')
+            .split('/')
+            .map(|segment| percent_decode(segment, false))
+            .collect::<Result<_>>()
+        {
+            Ok(segments) => segments,
+            Err(error) => return Response::error(400, &error.to_string()),
+        };
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+        match (request.method.as_str(), segments.as_slice()) {
+            ("POST", ["query"]) => self.query(request),
+            ("GET", ["tables"]) => match metadata::get_tables() {
+                Ok(tables) => Response::json(200, json!(tables)),
+                Err(error) => Response::error(500, &error.to_string()),
+            },
+            ("GET", ["tables", name]) => match metadata::get_table_metadata_by_name(name) {
+                Ok(table_metadata) => Response::json(200, json!(table_metadata)),
+                Err(error) => Response::lookup_error(&error),
+            },
+            ("GET", ["views"]) => match metadata::get_views() {
+                Ok(views) => Response::json(200, json!(views)),
+                Err(error) => Response::error(500, &error.to_string()),
+            },
+            ("GET", ["views", name]) => match metadata::get_view_metadata_by_name(name) {
+                Ok(view) => Response::json(200, json!(view)),
+                Err(error) => Response::lookup_error(&error),
+            },
+            (_, ["query"]) | (_, ["tables" | "views"]) | (_, ["tables" | "views", _]) => {
+                Response::error(405, "Method not allowed")
+            }
+            _ => Response::error(404, "Not found"),
+        }
+    }
+
+    /// Run the query in the request body
+    /// Results are JSON unless CSV is asked for with `?format=csv` or an
+    /// `Accept: text/csv` header
+    fn query(&self, request: &Request) -> Response {
+        let query = request.body.trim();
+        if query.is_empty() {
+            return Response::error(400, "Request body must contain a query");
+        }
+        let csv = match request.params.get("format").map(String::as_str) {
+            Some("csv") => true,
+            Some("json") => false,
+            Some(format) => {
+                return Response::error(400, &format!("Unknown result format {}", format))
+            }
+            None => request
+                .headers
+                .get("accept")
+                .is_some_and(|accept| accept.contains("text/csv")),
+        };
+
+        let result = {
+            let mut wings = self.wings.lock().unwrap();
+            wings
+                .register_from_catalog(query)
+                .and_then(|_| wings.query(query))
+        };
+        match result {
+            Ok(result) if csv => match result_to_csv(&result) {
+                Ok(body) => Response {
+                    status: 200,
+                    content_type: "text/csv",
+                    body,
+                },
+                Err(error) => Response::error(500, &error.to_string()),
+            },
+            Ok(result) => Response::json(200, result_to_json(&result)),
+            // Failing to read data isn't the client's fault
+            Err(error @ WingsError::Io(_)) => Response::error(500, &error.to_string()),
+            Err(error) => Response::error(400, &error.to_string()),
+        }
+    }
+}
+
+/// Reason to refuse a request that didn't come from localhost, or that a web
+/// page on another origin sent
+///
+/// Checking Host stops pages that rebind their own domain to 127.0.0.1.
+pub fn check_origin(request: &Request) -> Option<String> {
+    let host = request.headers.get("host");
+    if let Some(host) = host {
+        if !is_local_host(host) {
+            return Some(format!("Host {} isn't localhost", host));
+        }
+    }
+    let origin = request.headers.get("origin")?;
+    let same_origin =
+        host.is_some_and(|host| origin.strip_prefix("http://") == Some(host.as_str()));
+    if same_origin {
+        None
+    } else {
+        Some(format!("Requests from {} aren't allowed", origin))
+    }
+}
+
+/// Whether a Host header names localhost, with or without a port
+fn is_local_host(host: &str) -> bool {
+    let name = match host.rsplit_once(':') {
+        // The colons in an IPv6 address aren't a port
+        Some((name, port)) if !port.ends_with(']') => name,
+        _ => host,
+    };
+    LOCAL_HOSTS
+        .iter()
+        .any(|local| name.eq_ignore_ascii_case(local))
+}
+
+/// Read a request line, headers, and a body sized by Content-Length
+pub fn read_request(reader: &mut impl BufRead) -> Result<Request> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(WingsError::Protocol("Malformed request line".to_string()));
+    };
+
+    let mut headers: HashMap<String, String> = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let length: usize = match headers.get("content-length") {
+        Some(length) => length
+            .parse()
+            .map_err(|_| WingsError::Protocol(format!("Invalid Content-Length {}", length)))?,
+        None => 0,
+    };
+    if length > MAX_BODY_SIZE {
+        return Err(WingsError::Protocol(format!(
+            "Request body is larger than {} bytes",
+            MAX_BODY_SIZE
+        )));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+
+    let (path, query_string) = target.split_once('?').unwrap_or((target, ""));
+    let params = query_string
+        .split('&')
+        .filter_map(|param| param.split_once('='))
+        .map(|(key, value)| Ok((percent_decode(key, true)?, percent_decode(value, true)?)))
+        .collect::<Result<_>>()?;
+
+    Ok(Request {
+        method: method.to_uppercase(),
+        path: path.to_string(),
+        params,
+        headers,
+        body: String::from_utf8(body)
+            .map_err(|_| WingsError::Protocol("Request body isn't valid UTF-8".to_string()))?,
+    })
+}
+
+/// Decode `%XX` escapes in part of a URL, and `+` as a space in a query string
+pub fn percent_decode(text: &str, plus_as_space: bool) -> Result<String> {
+    let invalid = || WingsError::Protocol(format!("Invalid percent-encoding in {}", text));
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        match byte {
+            b'%' => {
+                let hex = rest
+                    .get(..2)
+                    .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                    .ok_or_else(invalid)?;
+                // Two hex digits are valid UTF-8 and always fit in a byte
+                let hex = std::str::from_utf8(hex).map_err(|_| invalid())?;
+                bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+                rest = &rest[2..];
+            }
+            b'+' if plus_as_space => bytes.push(b' '),
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+/// Result columns and rows as JSON, with each row as an array of values
+pub fn result_to_json(result: &QueryResult) -> JsonValue {
+    let rows: Vec<JsonValue> = result
+        .rows()
+        .iter()
+        .map(|row| row.values().iter().map(value_to_json).collect())
+        .collect();
+    json!({ "columns": result.columns(), "rows": rows })
+}
+
+fn value_to_json(value: &Value) -> JsonValue {
+    match value {
+        Value::Null => JsonValue::Null,
+        Value::Integer(i) => json!(i),
+        Value::Float(f) => json!(f),
+        Value::String(s) => json!(s),
+        Value::Binary(b) => json!(String::from_utf8_lossy(b)),
+    }
+}
+
+/// Result as CSV with a header row, NULL values are empty
+pub fn result_to_csv(result: &QueryResult) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(result.columns())?;
+    for row in result.rows() {
+        writer.write_record(row.values().iter().map(|value| match value {
+            Value::Null => String::new(),
+            value => crate::session::value_to_string(value),
+        }))?;
+    }
+    let bytes = writer
+        .into_inner()
+        .map_err(|error| WingsError::Io(error.into_error()))?;
+    String::from_utf8(bytes).map_err(|error| WingsError::Parse(error.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, HashMap},
+        env, fs,
+        io::Cursor,
+        path::PathBuf,
+    };
+
+    use crate::metadata::{self, Format, Metadata, TableMetadata};
+    use crate::server::{
+        percent_decode, read_request, result_to_csv, result_to_json, Request, Server,
+    };
+    use crate::{program, Wings};
+
+    #[test]
+    fn parse_request() {
+        let raw = "POST /query?format=csv HTTP/1.1\r\nHost: localhost\r\nContent-Length: 8\r\n\r\nselect 1";
+        let request = read_request(&mut Cursor::new(raw)).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/query");
+        assert_eq!(request.params.get("format").unwrap(), "csv");
+        assert_eq!(request.headers.get("host").unwrap(), "localhost");
+        assert_eq!(request.body, "select 1");
+
+        let raw = "POST /query?for%6Dat=c%73v&note=a+b%20c HTTP/1.1\r\n\r\n";
+        let request = read_request(&mut Cursor::new(raw)).unwrap();
+        assert_eq!(request.params.get("format").unwrap(), "csv");
+        assert_eq!(request.params.get("note").unwrap(), "a b c");
+        assert!(read_request(&mut Cursor::new("GET /tables?a=%zz HTTP/1.1\r\n\r\n")).is_err());
+    }
+
+    #[test]
+    fn decode_percent_escapes() {
+        assert_eq!(
+            percent_decode("billing%2Eevents", false).unwrap(),
+            "billing.events"
+        );
+        assert_eq!(percent_decode("my%20view+x", false).unwrap(), "my view+x");
+        assert_eq!(percent_decode("caf%C3%A9", true).unwrap(), "café");
+        assert!(percent_decode("%+f", false).is_err());
+        assert!(percent_decode("50%", false).is_err());
+        assert!(percent_decode("%FF", false).is_err());
+    }
+
+    #[test]
+    fn look_up_encoded_names() {
+        let dir = env::temp_dir().join(format!("wings-server-{}", std::process::id()));
+        program::set_test_catalog_dir(dir.clone());
+        program::init().unwrap();
+        let table_path = metadata::get_path_for_table("billing.events").unwrap();
+        fs::create_dir_all(table_path.parent().unwrap()).unwrap();
+        fs::write(
+            &table_path,
+            "metadata:\n  name: billing.events\n  format: csv\n  data_path: /data\nschema:\n  id: int\n",
+        )
+        .unwrap();
+        let server = Server::new(Wings::new().unwrap());
+        let get = |path: &str| {
+            server.respond(&Request {
+                method: "GET".to_string(),
+                path: path.to_string(),
+                params: HashMap::new(),
+                headers: HashMap::new(),
+                body: String::new(),
+            })
+        };
+        let found = get("/tables/billing%2Eevents");
+        let missing = get("/tables/no%20such%20table");
+        let invalid = get("/tables/billing%2");
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(found.status, 200);
+        assert!(found.body.contains("billing.events"));
+        assert_eq!(missing.status, 404);
+        assert!(missing.body.contains("no such table"));
+        assert_eq!(invalid.status, 400);
+    }
+
+    #[test]
+    fn results_as_json_and_csv() {
+        let mut wings = Wings::new().unwrap();
+        wings
+            .register_table(TableMetadata {
+                metadata: Metadata {
+                    name: "example".to_string(),
+                    format: Format::Csv,
+                    data_path: PathBuf::from("examples/data"),
+                    ..Default::default()
+                },
+                schema: [("id".to_string(), "int".to_string())].into(),
+                constraints: BTreeMap::new(),
+            })
+            .unwrap();
+        let result = wings
+            .query("select id, null as missing from example order by id limit 2")
+            .unwrap();
+
+        let json = result_to_json(&result);
+        assert_eq!(json["columns"], serde_json::json!(["id", "missing"]));
+        assert!(json["rows"][0][0].is_i64());
+        assert!(json["rows"][0][1].is_null());
+
+        let csv = result_to_csv(&result).unwrap();
+        assert!(csv.starts_with("id,missing\n"));
+        assert_eq!(csv.lines().count(), 3);
+    }
+
+    fn post_query(query: &str, headers: &[(&str, &str)]) -> Request {
+        Request {
+            method: "POST".to_string(),
+            path: "/query".to_string(),
+            params: HashMap::new(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            body: query.to_string(),
+        }
+    }
+
+    #[test]
+    fn refuse_other_origins() {
+        let mut wings = Wings::new().unwrap();
+        wings
+            .register_table(TableMetadata {
+                metadata: Metadata {
+                    name: "missing".to_string(),
+                    format: Format::Csv,
+                    data_path: PathBuf::from("./examples/no_such_dir"),
+                    ..Default::default()
+                },
+                schema: [("id".to_string(), "int".to_string())].into(),
+                constraints: BTreeMap::new(),
+            })
+            .unwrap();
+        let server = Server::new(wings);
+        let status =
+            |headers: &[(&str, &str)]| server.respond(&post_query("select 1", headers)).status;
+
+        assert_eq!(status(&[]), 200);
+        assert_eq!(status(&[("host", "localhost:8080")]), 200);
+        assert_eq!(
+            status(&[("host", "[::1]:8080"), ("origin", "http://[::1]:8080")]),
+            200
+        );
+        assert_eq!(status(&[("host", "attacker.example:8080")]), 403);
+        assert_eq!(
+            status(&[
+                ("host", "localhost:8080"),
+                ("origin", "http://attacker.example")
+            ]),
+            403
+        );
+        assert_eq!(
+            status(&[
+                ("host", "localhost:8080"),
+                ("origin", "http://localhost:3000")
+            ]),
+            403
+        );
+        assert_eq!(status(&[("origin", "null")]), 403);
+
+        // Data that can't be read is a server failure, a bad query isn't
+        assert_eq!(
+            server
+                .respond(&post_query("select * from missing", &[]))
+                .status,
+            500
+        );
+        assert_eq!(server.respond(&post_query("selec 1", &[])).status, 400);
+    }
+}
@@ -0,0 +1,1381 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    env,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use sqlite::{Connection, State, Value};
+
+use crate::{
+    data_loader,
+    error::{Result, WingsError},
+    explain::{LoadStrategy, QueryPlan, TablePlan},
+    formats::{FormatReader, FormatRegistry},
+    metadata::{self, Column, Format, Index, Metadata, TableMetadata, ViewMetadata},
+    program,
+    query_parser::{self, TableReference},
+    stats::{QueryStats, TableStats},
+    utils::{quote_identifier, quote_table_name},
+};
+
+/// Query session backed by an in-memory database
+///
+/// Tables are registered up front and only loaded the first time a query
+/// references them. Loaded tables stay in the session for later queries.
+pub struct Wings {
+    connection: Connection,
+    tables: BTreeMap<String, TableMetadata>,
+    views: BTreeMap<String, ViewMetadata>,
+    /// Tables and views registered from the catalog, which are refreshed
+    /// when their definition there changes
+    catalog_names: HashSet<String>,
+    /// Loaded tables and the row limit they were loaded with, None when complete
+    loaded: HashMap<String, Option<u64>>,
+    formats: FormatRegistry,
+    /// Report load progress on stderr
+    progress: bool,
+    /// Index the columns queries join and filter on
+    auto_index: bool,
+    /// Copies of the input read by tables registered with `register_input`
+    inputs: HashMap<String, SpooledInput>,
+}
+
+impl Wings {
+    /// Create a session with no tables registered
+    pub fn new() -> Result<Wings> {
+        let connection = sqlite::open(":memory:")?;
+        Ok(Wings {
+            connection,
+            tables: BTreeMap::new(),
+            views: BTreeMap::new(),
+            catalog_names: HashSet::new(),
+            loaded: HashMap::new(),
+            formats: FormatRegistry::default(),
+            progress: false,
+            auto_index: false,
+            inputs: HashMap::new(),
+        })
+    }
+
+    /// Add a format that registered tables can use
+    pub fn register_format(&mut self, reader: impl FormatReader + 'static) {
+        self.formats.register(reader);
+    }
+
+    /// Report progress on stderr while tables load
+    /// Nothing is reported when stderr isn't a terminal
+    pub fn set_progress(&mut self, progress: bool) {
+        self.progress = progress;
+    }
+
+    /// Index the columns each query joins or filters a table on, on top of
+    /// the indexes the table declares
+    pub fn set_auto_index(&mut self, auto_index: bool) {
+        self.auto_index = auto_index;
+    }
+
+    /// Formats available to this session
+    pub fn formats(&self) -> &FormatRegistry {
+        &self.formats
+    }
+
+    /// Register a table so queries can reference it by name
+    /// Registering a name again replaces the previous definition
+    pub fn register_table(&mut self, table_metadata: TableMetadata) -> Result<()> {
+        let name = table_metadata.metadata.name.clone();
+        if self.views.contains_key(&name) {
+            return Err(WingsError::Metadata(format!(
+                "{} is already registered as a view",
+                name
+            )));
+        }
+        if self.loaded.remove(&name).is_some() {
+            self.connection
+                .execute(format!("drop table if exists {}", quote_table_name(&name)))?;
+        }
+        self.tables.insert(name, table_metadata);
+        Ok(())
+    }
+
+    /// Register rows read from input, like a program's stdin, as a table
+    ///
+    /// The input is copied to a temporary file that's removed with the
+    /// session, since tables are read when a query first needs them. Columns
+    /// are inferred from the data when none are given.
+    pub fn register_input(
+        &mut self,
+        name: &str,
+        input: impl Read,
+        format: Format,
+        columns: Option<BTreeMap<String, Column>>,
+    ) -> Result<()> {
+        let spooled = SpooledInput::spool(input, &format)?;
+        let mut table_metadata = TableMetadata {
+            metadata: Metadata {
+                name: name.to_string(),
+                format,
+                data_path: spooled.path.clone(),
+                ..Default::default()
+            },
+            schema: BTreeMap::new(),
+            constraints: BTreeMap::new(),
+        };
+        match columns {
+            Some(columns) => table_metadata.set_columns(columns),
+            None => {
+                table_metadata.schema = data_loader::infer_schema(&table_metadata, &self.formats)?
+            }
+        }
+        table_metadata.validate()?;
+        self.register_table(table_metadata)?;
+        self.inputs.insert(name.to_string(), spooled);
+        Ok(())
+    }
+
+    /// Get the metadata for a registered table
+    pub fn table(&self, name: &str) -> Option<&TableMetadata> {
+        self.tables.get(name)
+    }
+
+    /// Register a view so queries can read it like a table
+    /// The tables it reads are loaded when a query reads the view
+    pub fn register_view(&mut self, view: ViewMetadata) -> Result<()> {
+        if self.tables.contains_key(&view.name) {
+            return Err(WingsError::Metadata(format!(
+                "{} is already registered as a table",
+                view.name
+            )));
+        }
+        // Only temporary views can read tables in other namespaces
+        let name = quote_identifier(&view.name);
+        self.connection
+            .execute(format!("drop view if exists {}", name))?;
+        self.connection
+            .execute(format!("create temp view {} as {}", name, view.query))?;
+        self.views.insert(view.name.clone(), view);
+        Ok(())
+    }
+
+    /// Get a registered view
+    pub fn view(&self, name: &str) -> Option<&ViewMetadata> {
+        self.views.get(name)
+    }
+
+    /// Register the catalog tables and views a query reads, and the tables
+    /// and views those views read in turn
+    ///
+    /// Definitions that changed in the catalog since they were registered are
+    /// registered again. Names registered some other way, like a table over
+    /// stdin, are left as they are.
+    pub fn register_from_catalog(&mut self, query: &str) -> Result<()> {
+        let mut names = query_parser::get_tables_from_query(query);
+        let mut seen: HashSet<String> = HashSet::new();
+        while let Some(name) = names.pop() {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            let registered = self.tables.contains_key(&name) || self.views.contains_key(&name);
+            if registered && !self.catalog_names.contains(&name) {
+                continue;
+            }
+            program::ensure_initialized()?;
+            if metadata::get_path_for_view(&name)?.exists() {
+                let view = metadata::get_view_metadata_by_name(&name)?;
+                names.extend(query_parser::get_tables_from_query(&view.query));
+                if self.views.get(&name) != Some(&view) {
+                    self.register_view(view)?;
+                }
+            } else {
+                let table_metadata = metadata::get_table_metadata_by_name(&name)?;
+                // Tables whose definition hasn't changed keep their loaded rows
+                if self.tables.get(&name) != Some(&table_metadata) {
+                    self.register_table(table_metadata)?;
+                }
+            }
+            self.catalog_names.insert(name);
+        }
+        Ok(())
+    }
+
+    /// The query and the queries of the views it reads, and of the views
+    /// those read in turn
+    fn expand_views(&self, query: &str) -> Vec<String> {
+        let mut queries = vec![query.to_string()];
+        let mut index = 0;
+        while index < queries.len() {
+            for name in query_parser::get_tables_from_query(&queries[index]) {
+                if let Some(view) = self.views.get(&name) {
+                    if !queries.contains(&view.query) {
+                        queries.push(view.query.clone());
+                    }
+                }
+            }
+            index += 1;
+        }
+        queries
+    }
+
+    /// Tables a query reads, directly or through views
+    fn query_tables(&self, query: &str) -> Vec<String> {
+        let mut tables: Vec<String> = Vec::new();
+        for query in self.expand_views(query) {
+            for name in query_parser::get_tables_from_query(&query) {
+                if !self.views.contains_key(&name) && !tables.contains(&name) {
+                    tables.push(name);
+                }
+            }
+        }
+        tables
+    }
+
+    /// Rows to load for a plain scan with a LIMIT, see [`query_parser::get_scan_limit`]
+    /// A view can filter or join, so queries that read one need every row
+    fn scan_limit(&self, query: &str) -> Option<u64> {
+        let reads_view = query_parser::get_tables_from_query(query)
+            .iter()
+            .any(|name| self.views.contains_key(name));
+        if reads_view {
+            None
+        } else {
+            query_parser::get_scan_limit(query)
+        }
+    }
+
+    /// Iterate over all registered tables
+    pub fn tables(&self) -> impl Iterator<Item = &TableMetadata> {
+        self.tables.values()
+    }
+
+    /// Run a query, loading any registered tables it references first
+    pub fn query(&mut self, query: &str) -> Result<QueryResult> {
+        self.query_with_params(query, &[])
+    }
+
+    /// Run a query with values bound to its `$1`, `$2`, ... parameters
+    ///
+    /// Files can be queried without registering a table, as `'data/*.csv'`
+    /// or `read_csv('data/')`. They're loaded into a table for this query only.
+    pub fn query_with_params(&mut self, query: &str, params: &[Value]) -> Result<QueryResult> {
+        let (query, file_tables) = self.file_tables(query)?;
+        let names: Vec<String> = file_tables
+            .iter()
+            .map(|table_metadata| table_metadata.metadata.name.clone())
+            .collect();
+        for table_metadata in file_tables {
+            self.register_table(table_metadata)?;
+        }
+        let result = self.run_query(&query, params);
+        for name in names {
+            self.tables.remove(&name);
+            if self.loaded.remove(&name).is_some() {
+                self.connection
+                    .execute(format!("drop table if exists {}", quote_table_name(&name)))?;
+            }
+        }
+        result
+    }
+
+    fn run_query(&mut self, query: &str, params: &[Value]) -> Result<QueryResult> {
+        if params.is_empty() {
+            if let Some(result) = self.count_from_files(query)? {
+                return Ok(result);
+            }
+        }
+        let tables = self.load_tables_for_query(query)?;
+
+        let started = Instant::now();
+        let mut statement = self.connection.prepare(query)?;
+        for (index, param) in params.iter().enumerate() {
+            let name = format!("${}", index + 1);
+            if let Some(position) = statement.parameter_index(&name)? {
+                statement.bind((position, param.clone()))?;
+            }
+        }
+        let columns: Arc<[String]> = statement.column_names().into();
+        let mut rows = Vec::new();
+        while let State::Row = statement.next()? {
+            let mut values = Vec::with_capacity(columns.len());
+            for index in 0..columns.len() {
+                values.push(statement.read::<Value, _>(index)?);
+            }
+            rows.push(Row {
+                columns: columns.clone(),
+                values,
+            });
+        }
+        Ok(QueryResult {
+            columns,
+            rows,
+            stats: QueryStats {
+                tables,
+                query_time: started.elapsed(),
+            },
+        })
+    }
+
+    /// Describe which tables and files a query would read and how SQLite
+    /// would run it, without loading any data
+    pub fn explain(&self, query: &str) -> Result<QueryPlan> {
+        let (query, file_tables) = self.file_tables(query)?;
+        let query = query.as_str();
+        let count_table = query_parser::get_count_query(query).map(|(table, _)| table);
+        let row_limit = self.scan_limit(query);
+        let plan_connection = self.schema_connection(query, &file_tables)?;
+        let queries = self.expand_views(query);
+
+        let mut tables: Vec<TablePlan> = Vec::new();
+        for table in self.query_tables(query) {
+            let Some(table_metadata) = find_table(&self.tables, &file_tables, &table) else {
+                continue;
+            };
+            data_loader::create_indexes(
+                &plan_connection,
+                table_metadata,
+                &table_metadata.metadata.indexes,
+            )?;
+            if self.auto_index {
+                data_loader::create_indexes(
+                    &plan_connection,
+                    table_metadata,
+                    &auto_indexes(&queries, table_metadata),
+                )?;
+            }
+
+            let reader = data_loader::get_reader(table_metadata, &self.formats)?;
+            let files: Vec<PathBuf> = data_loader::get_files(table_metadata)?
+                .into_iter()
+                .filter(|path| path.is_file() && reader.detect(path))
+                .collect();
+            let mut bytes = 0;
+            for file in &files {
+                bytes += fs::metadata(file)?.len();
+            }
+
+            let strategy = if count_table.as_ref() == Some(&table)
+                && self.loaded.get(&table) != Some(&None)
+                && table_metadata.loads_every_row()
+            {
+                LoadStrategy::CountFromFiles
+            } else {
+                self.load_strategy(&table, row_limit)
+            };
+            tables.push(TablePlan {
+                name: table,
+                format: table_metadata.metadata.format.to_string(),
+                data_path: table_metadata.metadata.data_path.clone(),
+                files,
+                bytes,
+                columns: table_metadata.columns(),
+                strategy,
+            });
+        }
+
+        let mut statement = plan_connection.prepare(format!("EXPLAIN QUERY PLAN {}", query))?;
+        let mut depths: HashMap<i64, usize> = HashMap::new();
+        let mut sqlite_plan: Vec<String> = Vec::new();
+        while let State::Row = statement.next()? {
+            let id = statement.read::<i64, _>(0)?;
+            let parent = statement.read::<i64, _>(1)?;
+            let detail = statement.read::<String, _>(3)?;
+            let depth = depths.get(&parent).map_or(0, |depth| depth + 1);
+            depths.insert(id, depth);
+            sqlite_plan.push(format!("{}{}", "  ".repeat(depth), detail));
+        }
+
+        Ok(QueryPlan {
+            tables,
+            sqlite_plan,
+        })
+    }
+
+    /// Names of the columns a query returns, found without loading any data
+    pub fn columns(&self, query: &str) -> Result<Vec<String>> {
+        let (query, file_tables) = self.file_tables(query)?;
+        let connection = self.schema_connection(&query, &file_tables)?;
+        let statement = connection.prepare(&query)?;
+        Ok(statement.column_names().to_vec())
+    }
+
+    /// Separate connection with the views a query reads and empty tables for
+    /// the tables it reads, which is enough for SQLite to prepare and plan it
+    fn schema_connection(&self, query: &str, file_tables: &[TableMetadata]) -> Result<Connection> {
+        let connection = sqlite::open(":memory:")?;
+        for view in self.views.values() {
+            connection.execute(format!(
+                "create temp view {} as {}",
+                quote_identifier(&view.name),
+                view.query
+            ))?;
+        }
+        for table in self.query_tables(query) {
+            if let Some(table_metadata) = find_table(&self.tables, file_tables, &table) {
+                data_loader::create_table_from_metadata(&connection, table_metadata)?;
+            }
+        }
+        Ok(connection)
+    }
+
+    /// Access the underlying database connection
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Tables for the files a query references directly, along with the
+    /// query rewritten to use their names
+    /// A quoted name of a registered table is left as a table
+    fn file_tables(&self, query: &str) -> Result<(String, Vec<TableMetadata>)> {
+        let mut rewritten = String::new();
+        let mut tables: Vec<TableMetadata> = Vec::new();
+        let referenced = query_parser::get_tables_from_query(query);
+        let mut end = 0;
+        for (reference, span) in query_parser::get_file_references(query) {
+            let TableReference::File { path, format } = reference else {
+                continue;
+            };
+            if format.is_none() && self.tables.contains_key(&path) {
+                continue;
+            }
+            let existing = tables.iter().find(|table_metadata| {
+                table_metadata.metadata.data_path == Path::new(&path)
+                    && format
+                        .as_deref()
+                        .is_none_or(|format| Format::from(format) == table_metadata.metadata.format)
+            });
+            let name = match existing {
+                // The same files read twice, like in a self join, are loaded once
+                Some(table_metadata) => table_metadata.metadata.name.clone(),
+                None => {
+                    let name = self.file_table_name(&tables, &referenced);
+                    let table_metadata = self.file_table(name, &path, format)?;
+                    let name = table_metadata.metadata.name.clone();
+                    tables.push(table_metadata);
+                    name
+                }
+            };
+            rewritten.push_str(&query[end..span.start]);
+            rewritten.push_str(&name);
+            end = span.end;
+        }
+        rewritten.push_str(&query[end..]);
+        Ok((rewritten, tables))
+    }
+
+    /// Name for a table over files that isn't a registered or loaded table
+    /// or view, one of the tables made for the same query, or a name the
+    /// query reads, ignoring case like SQLite does
+    fn file_table_name(&self, file_tables: &[TableMetadata], referenced: &[String]) -> String {
+        let taken = |name: &str| {
+            self.tables
+                .keys()
+                .chain(self.views.keys())
+                .chain(self.loaded.keys())
+                .chain(referenced)
+                .chain(file_tables.iter().map(|table| &table.metadata.name))
+                .any(|taken| taken.eq_ignore_ascii_case(name))
+        };
+        let mut number = file_tables.len();
+        loop {
+            number += 1;
+            let name = format!("_file_{}", number);
+            if !taken(&name) {
+                return name;
+            }
+        }
+    }
+
+    /// Table over the files at path, with its schema inferred from them
+    /// Without a format, it's detected from the path or the files in it
+    fn file_table(
+        &self,
+        name: String,
+        path: &str,
+        format: Option<String>,
+    ) -> Result<TableMetadata> {
+        let mut table_metadata = TableMetadata {
+            metadata: Metadata {
+                name,
+                data_path: PathBuf::from(path),
+                ..Default::default()
+            },
+            schema: BTreeMap::new(),
+            constraints: BTreeMap::new(),
+        };
+        let format = match format {
+            Some(format) => format,
+            None => {
+                let files = data_loader::get_files(&table_metadata)?;
+                let detected = self.formats.names().into_iter().find(|name| {
+                    self.formats.get(name).is_some_and(|reader| {
+                        reader.detect(Path::new(path))
+                            || files
+                                .iter()
+                                .any(|file| file.is_file() && reader.detect(file))
+                    })
+                });
+                detected.ok_or_else(|| {
+                    WingsError::Metadata(format!(
+                        "Can't tell the format of {}, read it with a function like read_csv('{}')",
+                        path, path
+                    ))
+                })?
+            }
+        };
+        table_metadata.metadata.format = Format::from(format.as_str());
+        table_metadata.schema = data_loader::infer_schema(&table_metadata, &self.formats)?;
+        Ok(table_metadata)
+    }
+
+    /// Answer an unfiltered count of a table that isn't loaded by counting
+    /// records in its files
+    fn count_from_files(&self, query: &str) -> Result<Option<QueryResult>> {
+        let Some((table, column)) = query_parser::get_count_query(query) else {
+            return Ok(None);
+        };
+        let Some(table_metadata) = self.tables.get(&table) else {
+            return Ok(None);
+        };
+        if self.loaded.get(&table) == Some(&None) || !table_metadata.loads_every_row() {
+            return Ok(None);
+        }
+
+        let table_stats = data_loader::count_table(table_metadata, &self.formats)?;
+        let columns: Arc<[String]> = vec![column].into();
+        let row = Row {
+            columns: columns.clone(),
+            values: vec![Value::Integer(table_stats.rows_read() as i64)],
+        };
+        Ok(Some(QueryResult {
+            columns,
+            rows: vec![row],
+            stats: QueryStats {
+                tables: vec![table_stats],
+                query_time: Duration::ZERO,
+            },
+        }))
+    }
+
+    /// How a registered table would be loaded for a query with the given scan limit
+    fn load_strategy(&self, table: &str, row_limit: Option<u64>) -> LoadStrategy {
+        match (self.loaded.get(table), row_limit) {
+            (Some(None), _) => LoadStrategy::Cached,
+            (Some(Some(loaded_limit)), Some(limit)) if limit <= *loaded_limit => {
+                LoadStrategy::Cached
+            }
+            (_, Some(limit)) => LoadStrategy::Limit(limit),
+            (_, None) => LoadStrategy::Full,
+        }
+    }
+
+    /// Load the tables a query needs, returning statistics for each table loaded
+    fn load_tables_for_query(&mut self, query: &str) -> Result<Vec<TableStats>> {
+        let mut tables: Vec<TableStats> = Vec::new();
+        // Plain scans with a LIMIT only need the first rows of their table
+        let row_limit = self.scan_limit(query);
+        let tables_read = self.query_tables(query);
+        for table in tables_read.iter().cloned() {
+            let Some(table_metadata) = self.tables.get(&table) else {
+                continue;
+            };
+            if self.load_strategy(&table, row_limit) == LoadStrategy::Cached {
+                continue;
+            }
+            if self.loaded.contains_key(&table) {
+                // Loaded with too few rows for this query, so start over
+                self.connection
+                    .execute(format!("drop table if exists {}", quote_table_name(&table)))?;
+            }
+            tables.push(data_loader::load_table(
+                &self.connection,
+                table_metadata,
+                &self.formats,
+                row_limit,
+                self.progress,
+            )?);
+            self.loaded.insert(table, row_limit);
+        }
+
+        // Tables loaded by earlier queries may need indexes for this one
+        if self.auto_index {
+            let queries = self.expand_views(query);
+            for table in tables_read {
+                let Some(table_metadata) = self.tables.get(&table) else {
+                    continue;
+                };
+                let indexes = auto_indexes(&queries, table_metadata);
+                let index_time =
+                    data_loader::create_indexes(&self.connection, table_metadata, &indexes)?;
+                if let Some(table_stats) = tables
+                    .iter_mut()
+                    .find(|table_stats| table_stats.name == table)
+                {
+                    table_stats.index_time += index_time;
+                }
+            }
+        }
+        Ok(tables)
+    }
+}
+
+/// Metadata for a table a query reads, from the tables made for the files it
+/// reads or else the registered tables
+fn find_table<'a>(
+    tables: &'a BTreeMap<String, TableMetadata>,
+    file_tables: &'a [TableMetadata],
+    name: &str,
+) -> Option<&'a TableMetadata> {
+    file_tables
+        .iter()
+        .find(|table_metadata| table_metadata.metadata.name == name)
+        .or_else(|| tables.get(name))
+}
+
+/// Input copied to a temporary file so it's read like any other table's files
+/// The file is removed when this is dropped
+struct SpooledInput {
+    path: PathBuf,
+}
+
+/// Numbers the files spooled by this process, which can have several sessions
+static SPOOLED_INPUTS: AtomicUsize = AtomicUsize::new(0);
+
+impl SpooledInput {
+    fn spool(mut input: impl Read, format: &Format) -> Result<SpooledInput> {
+        // The extension lets the format's reader detect the file
+        let path = env::temp_dir().join(format!(
+            "wings-input-{}-{}.{}",
+            process::id(),
+            SPOOLED_INPUTS.fetch_add(1, Ordering::Relaxed),
+            format
+        ));
+        let spooled = SpooledInput { path };
+        io::copy(&mut input, &mut File::create(&spooled.path)?)?;
+        Ok(spooled)
+    }
+}
+
+impl Drop for SpooledInput {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Single column indexes on the columns queries compare in a table, except
+/// those the table's declared indexes already start with
+fn auto_indexes(queries: &[String], table_metadata: &TableMetadata) -> Vec<Index> {
+    let columns = table_metadata.columns();
+    let table = &table_metadata.metadata.name;
+    let mut indexes: Vec<Index> = Vec::new();
+    let compared = queries
+        .iter()
+        .flat_map(|query| query_parser::get_compared_columns(query));
+    for (qualifier, column) in compared {
+        let in_table =
+            qualifier.is_none_or(|qualifier| &qualifier == table) && columns.contains(&column);
+        let indexed = table_metadata
+            .metadata
+            .indexes
+            .iter()
+            .chain(indexes.iter())
+            .any(|index| index.columns.first() == Some(&column));
+        if in_table && !indexed {
+            indexes.push(Index {
+                columns: vec![column],
+                unique: false,
+            });
+        }
+    }
+    indexes
+}
+
+/// Rows returned by a query along with their column names
+#[derive(Debug, Clone)]
+pub struct QueryResult {
+    columns: Arc<[String]>,
+    rows: Vec<Row>,
+    stats: QueryStats,
+}
+
+impl QueryResult {
+    /// Time spent loading tables and running the query
+    pub fn stats(&self) -> &QueryStats {
+        &self.stats
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn rows(&self) -> &[Row] {
+        &self.rows
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+}
+
+impl IntoIterator for QueryResult {
+    type Item = Row;
+    type IntoIter = std::vec::IntoIter<Row>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.rows.into_iter()
+    }
+}
+
+/// Single result row with typed values
+#[derive(Debug, Clone)]
+pub struct Row {
+    columns: Arc<[String]>,
+    values: Vec<Value>,
+}
+
+impl Row {
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
+    /// Get the value for a column by name
+    pub fn get(&self, column: &str) -> Option<&Value> {
+        self.columns
+            .iter()
+            .position(|c| c == column)
+            .map(|index| &self.values[index])
+    }
+
+    /// Convert the row to strings keyed by column, with NULL for missing values
+    pub fn to_string_map(&self) -> HashMap<String, String> {
+        self.columns
+            .iter()
+            .zip(self.values.iter())
+            .map(|(column, value)| (column.clone(), value_to_string(value)))
+            .collect()
+    }
+}
+
+/// Render a database value the way the CLI displays it
+pub fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::Null => "NULL".to_string(),
+        Value::Integer(i) => i.to_string(),
+        Value::Float(f) => f.to_string(),
+        Value::String(s) => s.clone(),
+        Value::Binary(b) => String::from_utf8_lossy(b).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use sqlite::Value;
+
+    use crate::error::Result;
+    use crate::explain::LoadStrategy;
+    use crate::formats::{FormatReader, Record, Records};
+    use crate::metadata::{
+        self, ColumnConstraints, DefaultValue, ErrorPolicy, Format, Index, Metadata, TableMetadata,
+        ViewMetadata,
+    };
+    use crate::{Wings, WingsError};
+
+    fn example_table() -> TableMetadata {
+        let mut schema: BTreeMap<String, String> = BTreeMap::new();
+        for column in ["col1", "col2", "col3", "col4", "col5", "created_at"] {
+            schema.insert(column.to_string(), "text".to_string());
+        }
+        schema.insert("id".to_string(), "int".to_string());
+        TableMetadata {
+            metadata: Metadata {
+                name: "test_table".to_string(),
+                format: Format::Csv,
+                data_path: PathBuf::from("./examples/data"),
+                ..Default::default()
+            },
+            schema,
+            constraints: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn query_registered_table() {
+        let mut wings = Wings::new().unwrap();
+        wings.register_table(example_table()).unwrap();
+
+        let result = wings
+            .query("select id, col1 from test_table order by id")
+            .unwrap();
+        assert_eq!(result.columns(), ["id", "col1"]);
+        assert_eq!(result.len(), 2);
+        assert_eq!(result.rows()[0].get("id"), Some(&Value::Integer(53)));
+        assert_eq!(
+            result.rows()[0].get("col1"),
+            Some(&Value::String("bcaeegfdeg".to_string()))
+        );
+    }
+
+    #[test]
+    fn query_with_params() {
+        let mut wings = Wings::new().unwrap();
+        wings.register_table(example_table()).unwrap();
+
+        let result = wings
+            .query_with_params(
+                "select col1 from test_table where id = $1",
+                &[Value::Integer(79)],
+            )
+            .unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(
+            result.rows()[0].get("col1"),
+            Some(&Value::String("fefgebcdca".to_string()))
+        );
+    }
+
+    #[test]
+    fn query_with_scan_limit() {
+        let mut wings = Wings::new().unwrap();
+        wings.register_table(example_table()).unwrap();
+
+        let result = wings.query("select * from test_table limit 1").unwrap();
+        assert_eq!(result.len(), 1);
+        // The limited load isn't enough for a full count, so the table is reloaded
+        let result = wings.query("select count(*) from test_table").unwrap();
+        assert_eq!(result.rows()[0].values(), [Value::Integer(2)]);
+    }
+
+    #[test]
+    fn limit_stops_at_first_file() {
+        let dir = std::env::temp_dir().join(format!("wings-limit-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // More rows than the loader can buffer, so reading has to stop here
+        let rows: String = (1..=20000).map(|id| format!("{}\n", id)).collect();
+        std::fs::write(dir.join("a.csv"), format!("id\n{}", rows)).unwrap();
+        std::fs::write(dir.join("b.csv"), "id\n0\n").unwrap();
+
+        let mut wings = Wings::new().unwrap();
+        wings
+            .register_table(TableMetadata {
+                metadata: Metadata {
+                    name: "numbers".to_string(),
+                    format: Format::Csv,
+                    data_path: dir.clone(),
+                    ..Default::default()
+                },
+                schema: [("id".to_string(), "int".to_string())].into(),
+                constraints: BTreeMap::new(),
+            })
+            .unwrap();
+        let result = wings.query("select id from numbers limit 3").unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let ids: Vec<&Value> = result.rows().iter().map(|row| &row.values()[0]).collect();
+        assert_eq!(
+            ids,
+            [&Value::Integer(1), &Value::Integer(2), &Value::Integer(3)]
+        );
+        let files: Vec<&Path> = result.stats().tables[0]
+            .files
+            .iter()
+            .map(|file| file.path.as_path())
+            .collect();
+        assert_eq!(files, [dir.join("a.csv")]);
+    }
+
+    #[test]
+    fn count_without_loading() {
+        let mut wings = Wings::new().unwrap();
+        wings.register_table(example_table()).unwrap();
+
+        let result = wings
+            .query("select count(*) as total from test_table")
+            .unwrap();
+        assert_eq!(result.columns(), ["total"]);
+        assert_eq!(result.rows()[0].values(), [Value::Integer(2)]);
+        assert_eq!(
+            result.stats().tables[0].strategy,
+            LoadStrategy::CountFromFiles
+        );
+        assert_eq!(result.stats().tables[0].files.len(), 2);
+        // Nothing was loaded, so the table doesn't exist in the database yet
+        assert!(wings
+            .connection()
+            .execute("select * from test_table")
+            .is_err());
+    }
+
+    #[test]
+    fn explain_without_loading() {
+        let mut wings = Wings::new().unwrap();
+        wings.register_table(example_table()).unwrap();
+
+        let plan = wings.explain("select * from test_table limit 1").unwrap();
+        assert_eq!(plan.tables.len(), 1);
+        assert_eq!(plan.tables[0].files.len(), 2);
+        assert_eq!(plan.tables[0].strategy, LoadStrategy::Limit(1));
+        assert_eq!(plan.sqlite_plan, vec!["SCAN test_table"]);
+        assert!(wings
+            .connection()
+            .execute("select * from test_table")
+            .is_err());
+
+        wings.query("select * from test_table").unwrap();
+        let plan = wings.explain("select * from test_table").unwrap();
+        assert_eq!(plan.tables[0].strategy, LoadStrategy::Cached);
+    }
+
+    #[test]
+    fn query_row_numbers() {
+        let mut wings = Wings::new().unwrap();
+        let mut table_metadata = example_table();
+        table_metadata.metadata.row_numbers = true;
+        wings.register_table(table_metadata).unwrap();
+
+        let result = wings
+            .query("select _row, id from test_table order by _row")
+            .unwrap();
+        let ids: Vec<&Value> = result.rows().iter().map(|row| &row.values()[1]).collect();
+        assert_eq!(ids, [&Value::Integer(53), &Value::Integer(79)]);
+    }
+
+    struct LineReader;
+
+    impl FormatReader for LineReader {
+        fn name(&self) -> &str {
+            "lines"
+        }
+
+        fn detect(&self, path: &Path) -> bool {
+            path.extension().is_some_and(|e| e == "csv")
+        }
+
+        fn infer_schema(&self, _path: &Path) -> Result<BTreeMap<String, String>> {
+            Ok(BTreeMap::from([("line".to_string(), "text".to_string())]))
+        }
+
+        fn read<'a>(&self, path: &'a Path) -> Result<Records<'a>> {
+            let contents = fs::read_to_string(path)?;
+            let rows: Vec<Result<Record>> = contents
+                .lines()
+                .map(|line| Ok(BTreeMap::from([("line".to_string(), line.to_string())])))
+                .collect();
+            Ok(Box::new(rows.into_iter()))
+        }
+    }
+
+    #[test]
+    fn query_registered_format() {
+        let mut wings = Wings::new().unwrap();
+        wings.register_format(LineReader);
+        let mut table_metadata = example_table();
+        table_metadata.metadata.format = Format::Custom("lines".to_string());
+        table_metadata.schema = LineReader.infer_schema(Path::new("")).unwrap();
+        wings.register_table(table_metadata).unwrap();
+
+        let result = wings.query("select count(*) from test_table").unwrap();
+        assert_eq!(result.rows()[0].values(), [Value::Integer(4)]);
+        let result = wings
+            .query("select count(*) from test_table where line like 'id%'")
+            .unwrap();
+        assert_eq!(result.rows()[0].values(), [Value::Integer(2)]);
+    }
+
+    #[test]
+    fn query_unknown_format() {
+        let mut wings = Wings::new().unwrap();
+        let mut table_metadata = example_table();
+        table_metadata.metadata.format = Format::Custom("tsv".to_string());
+        wings.register_table(table_metadata).unwrap();
+
+        let error = wings.query("select * from test_table").unwrap_err();
+        assert!(matches!(error, WingsError::Metadata(_)));
+    }
+
+    #[test]
+    fn query_stats() {
+        let mut wings = Wings::new().unwrap();
+        wings.register_table(example_table()).unwrap();
+
+        let result = wings.query("select * from test_table").unwrap();
+        let table_stats = &result.stats().tables[0];
+        assert_eq!(table_stats.files.len(), 2);
+        assert_eq!(table_stats.rows_read(), 2);
+        assert_eq!(table_stats.rows_inserted, 2);
+        assert_eq!(table_stats.bytes(), 232);
+
+        // Cached tables aren't loaded again
+        let result = wings.query("select * from test_table").unwrap();
+        assert!(result.stats().tables.is_empty());
+    }
+
+    #[test]
+    fn query_unregistered_table() {
+        let mut wings = Wings::new().unwrap();
+        assert!(wings.query("select * from missing").is_err());
+    }
+
+    #[test]
+    fn query_files_without_a_table() {
+        let mut wings = Wings::new().unwrap();
+
+        let result = wings
+            .query("select count(*) from 'examples/data/*.csv' a join read_csv('examples/data') b on a.id = b.id")
+            .unwrap();
+        assert_eq!(result.rows()[0].values(), [Value::Integer(2)]);
+        let result = wings
+            .query("select id from 'examples/data/datum_?.csv'")
+            .unwrap();
+        assert_eq!(result.rows()[0].get("id"), Some(&Value::Integer(79)));
+        // Nothing is left registered or loaded afterwards
+        assert_eq!(wings.tables().count(), 0);
+        assert!(wings.query("select * from _file_1").is_err());
+    }
+
+    #[test]
+    fn files_beside_a_table_named_like_them() {
+        let mut table_metadata = example_table();
+        table_metadata.metadata.name = "_FILE_1".to_string();
+        let mut wings = Wings::new().unwrap();
+        wings.register_table(table_metadata).unwrap();
+
+        let query = "select (select sum(id) from _FILE_1), (select count(*) from 'examples/avro_data/twitter.avro')";
+        let result = wings.query(query).unwrap();
+        assert_eq!(
+            result.rows()[0].values(),
+            [Value::Integer(132), Value::Integer(2)]
+        );
+        // The registered table is still there, loaded, for the next query
+        let result = wings.query("select count(*) from _FILE_1").unwrap();
+        assert_eq!(result.rows()[0].values(), [Value::Integer(2)]);
+        assert!(wings.table("_FILE_1").is_some());
+    }
+
+    #[test]
+    fn avro_booleans() {
+        let schema = apache_avro::Schema::parse_str(
+            r#"{"type": "record", "name": "flags", "fields": [
+                {"name": "id", "type": "long"},
+                {"name": "flag", "type": "boolean"}
+            ]}"#,
+        )
+        .unwrap();
+        let mut writer = apache_avro::Writer::new(&schema, Vec::new());
+        for (id, flag) in [(1i64, true), (2, false), (3, true)] {
+            let mut record = apache_avro::types::Record::new(&schema).unwrap();
+            record.put("id", id);
+            record.put("flag", flag);
+            writer.append(record).unwrap();
+        }
+        let path = std::env::temp_dir().join(format!("wings-flags-{}.avro", std::process::id()));
+        std::fs::write(&path, writer.into_inner().unwrap()).unwrap();
+
+        let mut wings = Wings::new().unwrap();
+        let query = format!(
+            "select sum(flag), count(*) from '{}' where flag = 1",
+            path.display()
+        );
+        let result = wings.query(&query);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            result.unwrap().rows()[0].values(),
+            [Value::Integer(2), Value::Integer(2)]
+        );
+    }
+
+    #[test]
+    fn columns_that_need_quoting() {
+        let dir = std::env::temp_dir().join(format!("wings-quoting-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("data.csv"),
+            "user id,order,created-at\n1,first,2024\n",
+        )
+        .unwrap();
+
+        let mut wings = Wings::new().unwrap();
+        wings
+            .register_table(TableMetadata {
+                metadata: Metadata {
+                    name: "events".to_string(),
+                    format: Format::Csv,
+                    data_path: dir.clone(),
+                    ..Default::default()
+                },
+                schema: [
+                    ("user id".to_string(), "int".to_string()),
+                    ("order".to_string(), "text".to_string()),
+                    ("created-at".to_string(), "text".to_string()),
+                ]
+                .into(),
+                constraints: BTreeMap::new(),
+            })
+            .unwrap();
+        let result = wings
+            .query("select \"user id\", \"order\", \"created-at\" from events")
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            result.rows()[0].values(),
+            [
+                Value::Integer(1),
+                Value::String("first".to_string()),
+                Value::String("2024".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn register_input() {
+        let input = "{\"level\": \"error\", \"code\": 500}\n{\"level\": \"info\", \"code\": 200}\n{\"level\": \"error\", \"code\": 503}\n";
+        let mut wings = Wings::new().unwrap();
+        wings
+            .register_input("stdin", input.as_bytes(), Format::Jsonl, None)
+            .unwrap();
+        let path = wings.table("stdin").unwrap().metadata.data_path.clone();
+        assert_eq!(wings.table("stdin").unwrap().schema["code"], "int");
+        let result = wings
+            .query("select level, count(*) from stdin group by level order by level")
+            .unwrap();
+        let rows: Vec<&[Value]> = result.rows().iter().map(|row| row.values()).collect();
+        assert_eq!(
+            rows,
+            [
+                [Value::String("error".to_string()), Value::Integer(2)],
+                [Value::String("info".to_string()), Value::Integer(1)]
+            ]
+        );
+
+        // Given columns replace the inferred ones, and the first copy is gone
+        let columns = metadata::parse_schema("level: text, code: text").unwrap();
+        wings
+            .register_input("stdin", input.as_bytes(), Format::Jsonl, Some(columns))
+            .unwrap();
+        assert!(!path.exists());
+        let result = wings.query("select max(code) from stdin").unwrap();
+        assert_eq!(
+            result.rows()[0].values(),
+            [Value::String("503".to_string())]
+        );
+
+        let path = wings.table("stdin").unwrap().metadata.data_path.clone();
+        drop(wings);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn constraints_and_error_policy() {
+        let dir = std::env::temp_dir().join(format!("wings-constraints-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("data.jsonl"),
+            "{\"id\": 1, \"amount\": 5}\n{\"id\": 2}\n{\"id\": 3, \"amount\": -1}\n{\"id\": 4, \"amount\": 7}\n",
+        )
+        .unwrap();
+
+        let mut table_metadata = TableMetadata {
+            metadata: Metadata {
+                name: "orders".to_string(),
+                format: Format::Jsonl,
+                data_path: dir.clone(),
+                ..Default::default()
+            },
+            schema: [
+                ("id".to_string(), "int".to_string()),
+                ("amount".to_string(), "int".to_string()),
+            ]
+            .into(),
+            constraints: [(
+                "amount".to_string(),
+                ColumnConstraints {
+                    default: Some(DefaultValue::Integer(0)),
+                    check: Some("amount >= 0".to_string()),
+                    ..Default::default()
+                },
+            )]
+            .into(),
+        };
+        let mut wings = Wings::new().unwrap();
+        wings.register_table(table_metadata.clone()).unwrap();
+        let failed = wings.query("select * from orders");
+
+        table_metadata.metadata.on_error = ErrorPolicy::Skip;
+        wings.register_table(table_metadata).unwrap();
+        let result = wings.query("select id, amount from orders order by id");
+        // Counts can't come from the files when rows may be left out
+        let count = wings.query("select count(*) from orders");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(failed, Err(WingsError::Schema(_))));
+        let result = result.unwrap();
+        let rows: Vec<&[Value]> = result.rows().iter().map(|row| row.values()).collect();
+        assert_eq!(
+            rows,
+            [
+                [Value::Integer(1), Value::Integer(5)],
+                [Value::Integer(2), Value::Integer(0)],
+                [Value::Integer(4), Value::Integer(7)]
+            ]
+        );
+        assert_eq!(result.stats().tables[0].rows_skipped, 1);
+        assert_eq!(count.unwrap().rows()[0].values(), [Value::Integer(3)]);
+    }
+
+    #[test]
+    fn failed_load_starts_over() {
+        let dir = std::env::temp_dir().join(format!("wings-failed-load-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let rows: String = (1..=2500).map(|id| format!("{},a\n", id)).collect();
+        // A short row after the first batch is committed
+        std::fs::write(dir.join("data.csv"), format!("id,name\n{}2501\n", rows)).unwrap();
+
+        let mut wings = Wings::new().unwrap();
+        wings
+            .register_table(TableMetadata {
+                metadata: Metadata {
+                    name: "ragged".to_string(),
+                    format: Format::Csv,
+                    data_path: dir.clone(),
+                    ..Default::default()
+                },
+                schema: [
+                    ("id".to_string(), "int".to_string()),
+                    ("name".to_string(), "text".to_string()),
+                ]
+                .into(),
+                constraints: BTreeMap::new(),
+            })
+            .unwrap();
+        let failed = wings.query("select count(*) from ragged where id > 0");
+        std::fs::write(dir.join("data.csv"), format!("id,name\n{}", rows)).unwrap();
+        let result = wings.query("select count(*), count(distinct id) from ragged");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(failed.is_err());
+        assert_eq!(
+            result.unwrap().rows()[0].values(),
+            [Value::Integer(2500), Value::Integer(2500)]
+        );
+    }
+
+    #[test]
+    fn indexes_after_load() {
+        let mut table_metadata = example_table();
+        table_metadata.metadata.indexes = vec![Index {
+            columns: vec!["created_at".to_string(), "id".to_string()],
+            unique: false,
+        }];
+        let mut wings = Wings::new().unwrap();
+        wings.register_table(table_metadata).unwrap();
+        wings.set_auto_index(true);
+
+        let query =
+            "select a.id from test_table a join test_table b on a.id = b.id where b.col1 = 'x'";
+        let plan = wings.explain(query).unwrap();
+        assert!(plan.sqlite_plan.iter().any(|line| line.contains("INDEX")));
+        wings.query(query).unwrap();
+
+        let mut statement = wings
+            .connection()
+            .prepare("select name from sqlite_master where type = 'index' order by name")
+            .unwrap();
+        let mut names: Vec<String> = Vec::new();
+        while let sqlite::State::Row = statement.next().unwrap() {
+            names.push(statement.read::<String, _>(0).unwrap());
+        }
+        assert_eq!(
+            names,
+            [
+                "idx_test_table_col1__4be828cb",
+                "idx_test_table_created_at_id__14325d63",
+                "idx_test_table_id__fd8cfe87"
+            ]
+        );
+    }
+
+    #[test]
+    fn query_through_views() {
+        let mut wings = Wings::new().unwrap();
+        wings.register_table(example_table()).unwrap();
+        wings
+            .register_view(ViewMetadata {
+                name: "big_ids".to_string(),
+                query: "select id from test_table where id > 60".to_string(),
+            })
+            .unwrap();
+        wings
+            .register_view(ViewMetadata {
+                name: "biggest".to_string(),
+                query: "select max(id) as id from big_ids".to_string(),
+            })
+            .unwrap();
+        assert!(wings
+            .register_table(TableMetadata {
+                metadata: Metadata {
+                    name: "big_ids".to_string(),
+                    ..Default::default()
+                },
+                schema: BTreeMap::new(),
+                constraints: BTreeMap::new(),
+            })
+            .is_err());
+
+        // The view filters, so a LIMIT on it can't limit what's loaded
+        let result = wings.query("select id from big_ids limit 1").unwrap();
+        assert_eq!(result.rows()[0].values(), [Value::Integer(79)]);
+        assert_eq!(result.stats().tables[0].strategy, LoadStrategy::Full);
+        let result = wings.query("select id from biggest").unwrap();
+        assert_eq!(result.rows()[0].values(), [Value::Integer(79)]);
+    }
+
+    #[test]
+    fn namespaced_tables() {
+        let mut wings = Wings::new().unwrap();
+        for name in ["billing.events", "logs.events"] {
+            let mut table_metadata = example_table();
+            table_metadata.metadata.name = name.to_string();
+            table_metadata.metadata.indexes = vec![Index {
+                columns: vec!["id".to_string()],
+                unique: true,
+            }];
+            wings.register_table(table_metadata).unwrap();
+        }
+        wings
+            .register_view(ViewMetadata {
+                name: "billed".to_string(),
+                query: "select id from billing.events".to_string(),
+            })
+            .unwrap();
+
+        let result = wings
+            .query("select count(*) from billing.events b join logs.events on b.id = events.id join billed using (id)")
+            .unwrap();
+        assert_eq!(result.rows()[0].values(), [Value::Integer(2)]);
+        let names: Vec<&str> = result
+            .stats()
+            .tables
+            .iter()
+            .map(|table| table.name.as_str())
+            .collect();
+        assert_eq!(names, ["billing.events", "logs.events"]);
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use crate::explain::{LoadStrategy, LISTED_FILES};
+
+/// Time spent reading one file and what was read from it
+#[derive(Debug, Clone, Default)]
+pub struct FileStats {
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub rows: u64,
+    /// Time spent parsing, summed across the file's chunks
+    pub parse_time: Duration,
+}
+
+/// Time spent on each stage of loading one table
+#[derive(Debug, Clone)]
+pub struct TableStats {
+    pub name: String,
+    pub strategy: LoadStrategy,
+    /// Time spent walking the data path and splitting files into chunks
+    pub discovery_time: Duration,
+    pub files: Vec<FileStats>,
+    /// Time the database writer spent inserting, not waiting for readers
+    pub insert_time: Duration,
+    pub rows_inserted: u64,
+    /// Rows left out for breaking a column constraint
+    pub rows_skipped: u64,
+    /// Time spent building indexes after the rows were inserted
+    pub index_time: Duration,
+    /// Wall clock time for the whole table
+    pub total_time: Duration,
+}
+
+impl TableStats {
+    pub fn new(name: &str, strategy: LoadStrategy) -> TableStats {
+        TableStats {
+            name: name.to_string(),
+            strategy,
+            discovery_time: Duration::ZERO,
+            files: Vec::new(),
+            insert_time: Duration::ZERO,
+            rows_inserted: 0,
+            rows_skipped: 0,
+            index_time: Duration::ZERO,
+            total_time: Duration::ZERO,
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.files.iter().map(|file| file.bytes).sum()
+    }
+
+    pub fn rows_read(&self) -> u64 {
+        self.files.iter().map(|file| file.rows).sum()
+    }
+
+    pub fn parse_time(&self) -> Duration {
+        self.files.iter().map(|file| file.parse_time).sum()
+    }
+}
+
+/// Statistics for every table a query loaded and for running the query
+#[derive(Debug, Clone, Default)]
+pub struct QueryStats {
+    pub tables: Vec<TableStats>,
+    pub query_time: Duration,
+}
+
+/// Collects per file statistics from reader threads
+#[derive(Debug, Default)]
+pub struct FileStatsCollector {
+    files: Mutex<BTreeMap<PathBuf, FileStats>>,
+}
+
+impl FileStatsCollector {
+    /// Add what was read from part of a file
+    pub fn record(&self, path: &Path, bytes: u64, rows: u64, parse_time: Duration) {
+        let mut files = self.files.lock().unwrap();
+        let file = files
+            .entry(path.to_path_buf())
+            .or_insert_with(|| FileStats {
+                path: path.to_path_buf(),
+                ..Default::default()
+            });
+        file.bytes += bytes;
+        file.rows += rows;
+        file.parse_time += parse_time;
+    }
+
+    /// Get the statistics for every file, in file order
+    pub fn into_files(self) -> Vec<FileStats> {
+        self.files.into_inner().unwrap().into_values().collect()
+    }
+}
+
+impl fmt::Display for TableStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} ({})", self.name, self.strategy)?;
+        writeln!(f, "  File discovery: {:?}", self.discovery_time)?;
+        writeln!(
+            f,
+            "  Files: {}, bytes: {}, rows read: {}",
+            self.files.len(),
+            self.bytes(),
+            self.rows_read()
+        )?;
+        writeln!(f, "  Parsing: {:?} across all files", self.parse_time())?;
+        let mut slowest: Vec<&FileStats> = self.files.iter().collect();
+        slowest.sort_by_key(|file| std::cmp::Reverse(file.parse_time));
+        for file in slowest.iter().take(LISTED_FILES) {
+            writeln!(
+                f,
+                "    {}: {} rows, {} bytes in {:?}",
+                file.path.display(),
+                file.rows,
+                file.bytes,
+                file.parse_time
+            )?;
+        }
+        if slowest.len() > LISTED_FILES {
+            writeln!(f, "    ... {} more", slowest.len() - LISTED_FILES)?;
+        }
+        writeln!(
+            f,
+            "  Insert: {} rows in {:?}",
+            self.rows_inserted, self.insert_time
+        )?;
+        if self.rows_skipped > 0 {
+            writeln!(
+                f,
+                "  Skipped: {} rows that broke a column constraint",
+                self.rows_skipped
+            )?;
+        }
+        if !self.index_time.is_zero() {
+            writeln!(f, "  Indexes: {:?}", self.index_time)?;
+        }
+        write!(f, "  Total: {:?}", self.total_time)
+    }
+}
+
+impl fmt::Display for QueryStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Stats\n--------------------")?;
+        for table in &self.tables {
+            writeln!(f, "{}", table)?;
+        }
+        write!(
+            f,
+            "--------------------\nQuery execution: {:?}",
+            self.query_time
+        )
+    }
+}
@@ -1,10 +1,7 @@
-use std::{collections::BTreeMap, fs::File, io::Error, path::Path};
-
-use apache_avro::{
-    from_value,
-    Reader,
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
 };
-use csv::{ReaderBuilder, StringRecord};
 
 use crate::metadata::TableMetadata;
 
@@ -13,76 +10,181 @@ pub fn schema_to_db(table_metadata: &TableMetadata) -> String {
     // Turn table metadata into sql create statement
     let create_table_statement = format!(
         "create table if not exists {} (\n{})",
-        table_metadata.metadata.name,
+        quote_table_name(&table_metadata.metadata.name),
         table_metadata.table_definition()
     );
-    return create_table_statement;
+    create_table_statement
 }
 
-pub fn read_path(path: &Path, format: &String) -> Result<Vec<BTreeMap<String, String>>, Error> {
-    // Open the file for given format
-    let mut rows: Vec<BTreeMap<String, String>> = Vec::new();
-    if path.is_file() {
-        if *format == String::from("csv") && path.extension().unwrap() == "csv" {
-            let file = File::open(path)?;
+/// Quote a table or column name for use in SQL
+/// Reserved words, spaces and punctuation are all kept as part of the name
+pub fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
 
-            // Create a CSV reader
-            let mut rdr = ReaderBuilder::new()
-                .has_headers(true) // Specify that the CSV file has headers
-                .from_reader(file);
-            #[allow(unused_assignments)]
-            let mut perm_headers = StringRecord::new();
-            {
-                // Read and print the headers
-                let headers = rdr.headers()?;
-                perm_headers = headers.clone();
-            }
-            // Iterate over each record (row) in the CSV file
-            for result in rdr.records() {
-                let mut row: BTreeMap<String, String> = BTreeMap::new();
-                // Extract the record
-                let record = result?;
-                for entry in record.iter().enumerate() {
-                    let key = perm_headers.get(entry.0).unwrap();
-                    row.insert(key.to_string(), entry.1.to_string());
+/// Split a table name like `billing.invoices` into its namespace and its
+/// name within the namespace
+pub fn split_table_name(name: &str) -> (Option<&str>, &str) {
+    match name.split_once('.') {
+        Some((namespace, table)) => (Some(namespace), table),
+        None => (None, name),
+    }
+}
+
+/// Quote a table name for use in SQL, with its namespace quoted separately
+/// as the database it's attached as
+pub fn quote_table_name(name: &str) -> String {
+    match split_table_name(name) {
+        (Some(namespace), table) => format!(
+            "{}.{}",
+            quote_identifier(namespace),
+            quote_identifier(table)
+        ),
+        (None, table) => quote_identifier(table),
+    }
+}
+
+/// Whether a path contains glob wildcards
+pub fn has_wildcard(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// Match a path against a glob pattern
+/// `*` and `?` match within a directory, `**` matches across directories,
+/// and `[abc]` matches one of a set of characters
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let path: Vec<char> = path.chars().collect();
+    glob_match_chars(&pattern, &path)
+}
+
+fn glob_match_chars(pattern: &[char], path: &[char]) -> bool {
+    match pattern.first() {
+        None => path.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            // `**/` also matches no directories at all
+            let rest = &pattern[2..];
+            let without_slash = rest.strip_prefix(&['/']).unwrap_or(rest);
+            glob_match_chars(without_slash, path)
+                || (0..path.len()).any(|index| glob_match_chars(rest, &path[index..]))
+        }
+        Some('*') => {
+            let rest = &pattern[1..];
+            for index in 0..=path.len() {
+                if glob_match_chars(rest, &path[index..]) {
+                    return true;
                 }
-                if row.len() > 0 {
-                    rows.push(row);
+                if path.get(index) == Some(&'/') {
+                    break;
                 }
             }
-        } else if *format == "avro" && path.extension().unwrap() == "avro" {
-            let f = File::open(path)?;
-            let r = Reader::new(f).unwrap();
-            for value in r {
-                match value {
-                    Ok(v) => {
-                        let mut row: BTreeMap<String, String> = BTreeMap::new();
-                        if let Ok(rec) = from_value::<serde_json::Value>(&v) {
-                            if let Some(object_row) = rec.as_object() {
-                                for column in object_row.keys() {
-                                    if let Some(val) = object_row.get(column) {
-                                        let mut record: String = String::from("");
-                                        if val.is_string() {
-                                            record = val.to_string();
-                                            // Trim quotes
-                                            let mut chars = record.chars();
-                                            chars.next();
-                                            chars.next_back();
-                                            record = chars.as_str().to_string();
-                                        } else {
-                                           record = val.to_string();
-                                        }
-                                        row.insert(column.clone(), record);
-                                    }
-                                }
-                                rows.push(row);
-                            }
-                        }
-                    }
-                    Err(e) => println!("Error: {}", e),
-                };
-            }
+            false
+        }
+        Some('?') => {
+            matches!(path.first(), Some(c) if *c != '/')
+                && glob_match_chars(&pattern[1..], &path[1..])
+        }
+        Some('[') => {
+            let Some(close) = pattern.iter().position(|c| *c == ']') else {
+                return path.first() == Some(&'[') && glob_match_chars(&pattern[1..], &path[1..]);
+            };
+            matches!(path.first(), Some(c) if pattern[1..close].contains(c))
+                && glob_match_chars(&pattern[close + 1..], &path[1..])
+        }
+        Some(c) => path.first() == Some(c) && glob_match_chars(&pattern[1..], &path[1..]),
+    }
+}
+
+/// Resolve `.` and `..` in a path without looking at the file system
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                // Nothing is above the root
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => normalized.push(".."),
+            },
+            component => normalized.push(component),
         }
     }
-    Ok(rows)
+    normalized
+}
+
+/// Resolve symlinks, `.` and `..` in a path through the file system
+/// Everything from the first component with a wildcard on is left as it is,
+/// since it names files that may not exist yet
+pub fn canonicalize_path(path: &Path) -> io::Result<PathBuf> {
+    let wildcard = path
+        .components()
+        .position(|component| has_wildcard(&component.as_os_str().to_string_lossy()))
+        .unwrap_or(path.components().count());
+    let prefix: PathBuf = path.components().take(wildcard).collect();
+    let mut canonical = prefix.canonicalize()?;
+    canonical.extend(path.components().skip(wildcard));
+    Ok(canonical)
+}
+
+/// Path that leads from base to path, worked out from their components alone
+pub fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let path = normalize_path(path);
+    let base = normalize_path(base);
+    let common = path
+        .components()
+        .zip(base.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative = PathBuf::new();
+    for _ in base.components().skip(common) {
+        relative.push("..");
+    }
+    for component in path.components().skip(common) {
+        relative.push(component);
+    }
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    relative
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::utils::{glob_match, normalize_path, relative_path};
+
+    #[test]
+    fn match_globs() {
+        assert!(glob_match("data/*.csv", "data/a.csv"));
+        assert!(!glob_match("data/*.csv", "data/nested/a.csv"));
+        assert!(glob_match("data/**/*.csv", "data/a.csv"));
+        assert!(glob_match("data/**/*.csv", "data/nested/deeper/a.csv"));
+        assert!(glob_match("data/day_?.jsonl", "data/day_1.jsonl"));
+        assert!(glob_match("data/[ab].csv", "data/b.csv"));
+        assert!(!glob_match("data/[ab].csv", "data/c.csv"));
+    }
+
+    #[test]
+    fn relative_paths() {
+        assert_eq!(normalize_path(Path::new("/a/./b/../c")), Path::new("/a/c"));
+        assert_eq!(normalize_path(Path::new("/../a")), Path::new("/a"));
+        assert_eq!(
+            normalize_path(Path::new("../a/../../b")),
+            Path::new("../../b")
+        );
+        let base = Path::new("/work/project/.wings/tables");
+        assert_eq!(
+            relative_path(Path::new("/work/project/data/*.csv"), base),
+            Path::new("../../data/*.csv")
+        );
+        assert_eq!(
+            relative_path(Path::new("/work/project/.wings/tables/./x"), base),
+            Path::new("x")
+        );
+        assert_eq!(relative_path(base, base), Path::new("."));
+    }
 }